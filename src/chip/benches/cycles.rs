@@ -0,0 +1,68 @@
+//! Measures instructions executed per second.
+//!
+//! Run with `cargo bench`.
+
+extern crate chip;
+
+use chip::{Chip, Quirks, Framebuffer, Timer, Video, Audio, Input, Machine};
+use std::time::{Duration, Instant};
+
+/// Does nothing, so that only the chip is measured.
+struct Null;
+
+impl Timer for Null {
+    fn pump(&mut self) -> bool { false }
+}
+
+impl Video for Null {
+    fn present(&mut self, _: &Framebuffer) {}
+}
+
+impl Audio for Null {
+    fn buzz(&mut self, _: bool) {}
+    fn pattern(&mut self, _: &[u8; 16], _: u8) {}
+}
+
+impl Input for Null {
+    fn keydown(&self, _: usize) -> bool { false }
+}
+
+impl Machine for Null {
+    fn sys(&mut self, _: u16) {}
+}
+
+/// A busy loop mixing arithmetic, skips, memory access and drawing.
+const PROGRAM: &[u8] = &[
+    0x60, 0x00,     // 200: V0 := 0
+    0x61, 0x01,     //      V1 := 1
+    0x80, 0x14,     // 204: V0 += V1
+    0x72, 0x01,     //      V2 += 1
+    0x32, 0x00,     //      if V2 != 0 then
+    0x12, 0x04,     //          jump 204
+    0xA3, 0x00,     //      I := 300
+    0xF2, 0x33,     //      bcd V2
+    0xD0, 0x15,     //      sprite V0 V1 5
+    0x12, 0x00,     //      jump 200
+];
+
+const CYCLES_PER_FRAME: usize = 10_000;
+const DURATION: u64 = 3;
+
+fn main() {
+    let mut chip = Chip::default();
+    chip.set_quirks(Quirks::CHIP48);    // no display wait
+    chip.load(0x200, PROGRAM);
+
+    let mut null = Null;
+    let mut frames = 0;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(DURATION) {
+        chip.frame(CYCLES_PER_FRAME, &mut null).unwrap();
+        frames += 1;
+    }
+
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+    let cycles = frames * CYCLES_PER_FRAME;
+    println!("{} cycles in {:.3} s: {:.0} cycles/s", cycles, secs, cycles as f64 / secs);
+}
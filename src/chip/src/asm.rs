@@ -0,0 +1,710 @@
+//! Assembler for the Octo syntax.
+//!
+//! Supported: labels (`: name`), `:const`, `:alias`, `:macro`, `:org`,
+//! `:byte`, `:call`, bare numbers as bytes, every instruction form of
+//! CHIP-8, SUPER-CHIP and XO-CHIP, and the structured `if ... begin ...
+//! else ... end` and `loop ... while ... again` forms.
+//!
+//! Not supported: expressions (`:calc`), `:unpack`, `:next`, string mode,
+//! and the `<`, `>`, `<=`, `>=` comparisons which clobber VF.
+//!
+//! Unlike Octo, no jump to `main` is inserted: the program starts with
+//! its first byte, at `0x200`.
+
+use instruction::{self, Instruction};
+use instruction::Instruction::*;
+use std::collections::{HashMap, VecDeque};
+use std::error;
+use std::fmt;
+use std::result;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use disasm::disassemble;
+
+    fn error(src: &str) -> (usize, usize, String) {
+        let e = assemble(src).unwrap_err();
+        (e.line, e.col, e.msg)
+    }
+
+    #[test]
+    fn forms() {
+        let rom = assemble("
+            : main
+                clear
+                v0 := 5  v1 := v0  v2 := random 0xF0  v3 := delay  v4 := key
+                v0 += 1  v0 -= 1  v0 += v1  v0 -= v1  v0 =- v1
+                v0 |= v1  v0 &= v1  v0 ^= v1  v0 >>= v1  v0 <<= v1
+                i := main  i := long main  i := hex v5  i := bighex v5  i += v6
+                delay := v7  buzzer := v8  pitch := v9
+                sprite va vb 15  bcd vc  save vd  load ve  save v1 - v2  load v2 - v1
+                saveflags vf  loadflags v0  plane 3  audio
+                if v0 == 1 then if v0 != 2 then if v0 == v1 then if v0 != v1 then
+                if v0 key then if v0 -key then
+                scroll-down 4  scroll-up 2  scroll-left  scroll-right  lores  hires  exit
+                jump main  jump0 main  main  :call main  return ;
+        ").unwrap();
+        assert_eq!(rom, vec![
+            0x00, 0xE0,
+            0x60, 0x05, 0x81, 0x00, 0xC2, 0xF0, 0xF3, 0x07, 0xF4, 0x0A,
+            0x70, 0x01, 0x70, 0xFF, 0x80, 0x14, 0x80, 0x15, 0x80, 0x17,
+            0x80, 0x11, 0x80, 0x12, 0x80, 0x13, 0x80, 0x16, 0x80, 0x1E,
+            0xA2, 0x00, 0xF0, 0x00, 0x02, 0x00, 0xF5, 0x29, 0xF5, 0x30, 0xF6, 0x1E,
+            0xF7, 0x15, 0xF8, 0x18, 0xF9, 0x3A,
+            0xDA, 0xBF, 0xFC, 0x33, 0xFD, 0x55, 0xFE, 0x65, 0x51, 0x22, 0x52, 0x13,
+            0xFF, 0x75, 0xF0, 0x85, 0xF3, 0x01, 0xF0, 0x02,
+            0x40, 0x01, 0x30, 0x02, 0x90, 0x10, 0x50, 0x10,
+            0xE0, 0xA1, 0xE0, 0x9E,
+            0x00, 0xC4, 0x00, 0xD2, 0x00, 0xFC, 0x00, 0xFB, 0x00, 0xFE, 0x00, 0xFF, 0x00, 0xFD,
+            0x12, 0x00, 0xB2, 0x00, 0x22, 0x00, 0x22, 0x00, 0x00, 0xEE, 0x00, 0xEE,
+        ]);
+    }
+
+    #[test]
+    fn data() {
+        let rom = assemble(":const N 3 :byte N 0x10 0b11 -1 :org 0x206 255").unwrap();
+        assert_eq!(rom, vec![3, 0x10, 3, 0xFF, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn forward_labels() {
+        let rom = assemble("jump later i := long later : later").unwrap();
+        assert_eq!(rom, vec![0x12, 0x06, 0xF0, 0x00, 0x02, 0x06]);
+    }
+
+    #[test]
+    fn aliases_and_macros() {
+        let rom = assemble("
+            :alias x v3
+            :macro twice reg n { reg += n reg += n }
+            twice x 2
+        ").unwrap();
+        assert_eq!(rom, vec![0x73, 0x02, 0x73, 0x02]);
+    }
+
+    #[test]
+    fn blocks() {
+        let rom = assemble("
+            loop
+                while v0 != 0
+                if v1 == 1 begin v2 := 1 else v2 := 2 end
+            again
+        ").unwrap();
+        assert_eq!(rom, vec![
+            0x40, 0x00, 0x12, 0x10,     // 200: while
+            0x31, 0x01, 0x12, 0x0C,     // 204: if begin
+            0x62, 0x01, 0x12, 0x0E,     // 208:     else
+            0x62, 0x02,                 // 20C:     end
+            0x12, 0x00,                 // 20E: again
+        ]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let rom = assemble("
+            : main
+                i := long pixel
+                v0 := 0
+            : draw
+                sprite v0 v0 1
+                v0 += 1
+                if v0 != 32 then jump draw
+                0x01 0x23
+                subroutine
+                exit
+            : subroutine
+                return
+            : pixel
+                0x80
+        ").unwrap();
+        assert_eq!(assemble(&disassemble(&rom, 0x200).to_string()).unwrap(), rom);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("clear\n  v0 := 256"), (2, 9, "0x100 does not fit into a byte".to_string()));
+        assert_eq!(error("jump nowhere"), (1, 6, "undefined name nowhere".to_string()));
+        assert_eq!(error("sprite v0 v1"), (1, 13, "expected a number at the end".to_string()));
+        assert_eq!(error("sprite v0 v1 v2"), (1, 14, "expected a number after v1".to_string()));
+        assert_eq!(error(": a : a"), (1, 7, "a is already defined".to_string()));
+        assert_eq!(error("v0 ** v1"), (1, 4, "unknown operator **".to_string()));
+        assert_eq!(error("again"), (1, 1, "again without loop".to_string()));
+        assert_eq!(error("loop"), (1, 1, "loop without again".to_string()));
+        assert_eq!(error(":macro m { m } m"), (1, 12, "macro expansion too deep".to_string()));
+        assert_eq!(error(":org 0xFFFF 1 2"), (1, 15, "past the end of the address space".to_string()));
+        assert_eq!(error(":org 0xFFFE clear clear"), (1, 19, "past the end of the address space".to_string()));
+        assert_eq!(error(":org 0xFFFA if v0 == 0 begin 1 2 end"), (1, 34, "past the end of the address space".to_string()));
+        assert_eq!(error(":org 0xFFFE loop while v0 == 0 again"), (1, 18, "past the end of the address space".to_string()));
+        assert_eq!(error(":org 0x1000 loop clear again"), (1, 24, "0x1000 does not fit into an address".to_string()));
+        assert_eq!(error(":org 0xFFC if v0 == 0 begin end"), (1, 29, "0x1000 does not fit into an address".to_string()));
+    }
+}
+
+/// An error in the source, at `line` and `col`, both counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
+}
+
+impl error::Error for Error {}
+
+/// Where programs are loaded.
+const ORIGIN: u16 = 0x200;
+
+/// Bound on macro expansions, to stop runaway recursion.
+const MAX_EXPANSIONS: usize = 10000;
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Token<'a> {
+    fn error<T, S: Into<String>>(&self, msg: S) -> Result<T> {
+        Err(Error { line: self.line, col: self.col, msg: msg.into() })
+    }
+}
+
+fn tokenize(src: &str) -> VecDeque<Token<'_>> {
+    let mut tokens = VecDeque::new();
+    for (n, line) in src.lines().enumerate() {
+        let mut start = None;
+        for (col, (i, c)) in line.char_indices().chain(Some((line.len(), ' '))).enumerate() {
+            match (start, c.is_whitespace()) {
+                (None, false) if c == '#' => break,
+                (None, false) => start = Some((col, i)),
+                (Some((col, s)), true) => {
+                    tokens.push_back(Token { text: &line[s..i], line: n + 1, col: col + 1 });
+                    start = None;
+                },
+                _ => (),
+            }
+        }
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<i32> {
+    let (neg, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let n = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i32::from_str_radix(bin, 2)
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse()
+    } else {
+        return None;
+    };
+    n.ok().map(|n| if neg { -n } else { n })
+}
+
+/// Operand to patch once every label is known.
+enum Width {
+    /// Low 12 bits of an instruction.
+    Addr,
+    /// A whole word, after `F000`.
+    Long,
+}
+
+struct Macro<'a> {
+    args: Vec<&'a str>,
+    body: Vec<Token<'a>>,
+}
+
+/// Open `if ... begin` or `loop`, with the jumps to patch at its end.
+enum Block {
+    If { jump: u16 },
+    Else { jump: u16 },
+    Loop { start: u16, breaks: Vec<u16> },
+}
+
+struct Assembler<'a> {
+    tokens: VecDeque<Token<'a>>,
+    last: Token<'a>,
+    rom: Vec<u8>,
+    here: u16,
+    labels: HashMap<&'a str, u16>,
+    consts: HashMap<&'a str, i32>,
+    aliases: HashMap<&'a str, u8>,
+    macros: HashMap<&'a str, Macro<'a>>,
+    expansions: usize,
+    fixups: Vec<(u16, Width, Token<'a>)>,
+    blocks: Vec<(Block, Token<'a>)>,
+}
+
+/// Assemble `src` into a ROM image to load at `0x200`.
+pub fn assemble(src: &str) -> Result<Vec<u8>> {
+    let mut asm = Assembler {
+        tokens: tokenize(src),
+        last: Token { text: "", line: 1, col: 1 },
+        rom: vec![],
+        here: ORIGIN,
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        fixups: vec![],
+        blocks: vec![],
+    };
+    while let Some(tok) = asm.tokens.pop_front() {
+        asm.last = tok;
+        asm.statement(tok)?;
+    }
+    if let Some(&(ref block, tok)) = asm.blocks.last() {
+        return tok.error(match *block {
+            Block::Loop { .. } => "loop without again",
+            _ => "begin without end",
+        });
+    }
+    asm.resolve()?;
+    Ok(asm.rom)
+}
+
+impl<'a> Assembler<'a> {
+    fn next(&mut self, what: &str) -> Result<Token<'a>> {
+        match self.tokens.pop_front() {
+            Some(tok) => {
+                self.last = tok;
+                Ok(tok)
+            },
+            None => {
+                let end = Token { col: self.last.col + self.last.text.chars().count(), ..self.last };
+                end.error(format!("expected {} at the end", what))
+            },
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.front().map(|t| t.text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let after = self.last.text;
+        let tok = self.next(text)?;
+        if tok.text == text { Ok(()) } else { tok.error(format!("expected {} after {}", text, after)) }
+    }
+
+    /// Index in the ROM of `addr`, failing at `tok` if `addr` wrapped
+    /// around past 0xFFFF, below `ORIGIN`.
+    fn offset(addr: u16, tok: Token) -> Result<usize> {
+        match addr.checked_sub(ORIGIN) {
+            Some(i) => Ok(i as usize),
+            None => tok.error("past the end of the address space"),
+        }
+    }
+
+    fn byte(&mut self, value: u8) -> Result<()> {
+        let i = Assembler::offset(self.here, self.last)?;
+        if self.rom.len() <= i {
+            self.rom.resize(i + 1, 0);
+        }
+        self.rom[i] = value;
+        // Past 0xFFFF to 0, below `ORIGIN`, for the next byte to fail.
+        self.here = self.here.wrapping_add(1);
+        Ok(())
+    }
+
+    fn word(&mut self, value: u16) -> Result<()> {
+        self.byte((value >> 8) as u8)?;
+        self.byte(value as u8)
+    }
+
+    fn emit(&mut self, inst: Instruction) -> Result<()> {
+        self.word(instruction::encode(&inst))
+    }
+
+    /// Emit `inst` with the address named by the next token.
+    fn emit_addr(&mut self, inst: Instruction) -> Result<()> {
+        let tok = self.next("an address")?;
+        self.fixups.push((self.here, Width::Addr, tok));
+        self.emit(inst)
+    }
+
+    fn define(&mut self, tok: Token<'a>) -> Result<&'a str> {
+        let name = tok.text;
+        if number(name).is_some() || self.register(name).is_some() {
+            return tok.error(format!("{} is not a valid name", name));
+        }
+        if self.labels.contains_key(name) || self.consts.contains_key(name)
+            || self.aliases.contains_key(name) || self.macros.contains_key(name) {
+            return tok.error(format!("{} is already defined", name));
+        }
+        Ok(name)
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(&x) = self.aliases.get(text) {
+            return Some(x);
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next().and_then(|c| c.to_digit(16)), chars.next()) {
+            (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => Some(x as u8),
+            _ => None,
+        }
+    }
+
+    fn reg(&mut self) -> Result<u8> {
+        let after = self.last.text;
+        let tok = self.next("a register")?;
+        match self.register(tok.text) {
+            Some(x) => Ok(x),
+            None => tok.error(format!("expected a register after {}, found {}", after, tok.text)),
+        }
+    }
+
+    /// Value known now: a number, a constant or a label already defined.
+    fn value(&mut self, min: i32, max: i32, what: &str) -> Result<i32> {
+        let after = self.last.text;
+        let tok = self.next("a number")?;
+        let n = match number(tok.text)
+            .or_else(|| self.consts.get(tok.text).cloned())
+            .or_else(|| self.labels.get(tok.text).map(|&a| a as i32)) {
+            Some(n) => n,
+            None if self.register(tok.text).is_some() => return tok.error(format!("expected a number after {}", after)),
+            None => return tok.error(format!("undefined name {}", tok.text)),
+        };
+        if !(min..=max).contains(&n) {
+            return tok.error(format!("{:#X} does not fit into {}", n, what));
+        }
+        Ok(n)
+    }
+
+    fn nn(&mut self) -> Result<u8> {
+        self.value(-128, 0xFF, "a byte").map(|n| n as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        self.value(0, 0xF, "a nibble").map(|n| n as u8)
+    }
+
+    fn statement(&mut self, tok: Token<'a>) -> Result<()> {
+        match tok.text {
+            ":" => {
+                let name = self.next("a label")?;
+                let name = self.define(name)?;
+                self.labels.insert(name, self.here);
+            },
+            ":const" => {
+                let name = self.next("a name")?;
+                let name = self.define(name)?;
+                let value = self.value(-0x8000, 0xFFFF, "a word")?;
+                self.consts.insert(name, value);
+            },
+            ":alias" => {
+                let name = self.next("a name")?;
+                let name = self.define(name)?;
+                let x = self.reg()?;
+                self.aliases.insert(name, x);
+            },
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let addr = self.value(ORIGIN as i32, 0xFFFF, "the address space")?;
+                self.here = addr as u16;
+            },
+            ":byte" => {
+                let nn = self.nn()?;
+                self.byte(nn)?;
+            },
+            ":call" => self.emit_addr(Call(0))?,
+            "clear" => self.emit(Clear)?,
+            "return" | ";" => self.emit(Return)?,
+            "scroll-down" => { let n = self.nibble()?; self.emit(ScrollDown(n))? },
+            "scroll-up" => { let n = self.nibble()?; self.emit(ScrollUp(n))? },
+            "scroll-right" => self.emit(ScrollRight)?,
+            "scroll-left" => self.emit(ScrollLeft)?,
+            "exit" => self.emit(Exit)?,
+            "lores" => self.emit(LoRes)?,
+            "hires" => self.emit(HiRes)?,
+            "audio" => self.emit(Audio)?,
+            "jump" => self.emit_addr(Jump(0))?,
+            "jump0" => self.emit_addr(JumpOffset(0))?,
+            "sprite" => {
+                let x = self.reg()?;
+                let y = self.reg()?;
+                let n = self.nibble()?;
+                self.emit(Draw { x, y, n })?;
+            },
+            "plane" => {
+                let n = self.value(0, 3, "a plane mask")?;
+                self.emit(Planes(n as u8))?;
+            },
+            "bcd" => { let x = self.reg()?; self.emit(Bcd { x })? },
+            "saveflags" => { let x = self.reg()?; self.emit(SaveFlags { x })? },
+            "loadflags" => { let x = self.reg()?; self.emit(LoadFlags { x })? },
+            "save" | "load" => {
+                let x = self.reg()?;
+                let inst = if self.peek() == Some("-") {
+                    self.next("-")?;
+                    let y = self.reg()?;
+                    if tok.text == "save" { SaveRange { x, y } } else { LoadRange { x, y } }
+                } else if tok.text == "save" {
+                    Store { x }
+                } else {
+                    Load { x }
+                };
+                self.emit(inst)?;
+            },
+            "i" => self.index()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.reg()?;
+                self.emit(match tok.text {
+                    "delay" => SetDelay { x },
+                    "buzzer" => SetSound { x },
+                    _ => Pitch { x },
+                })?;
+            },
+            "if" => {
+                let skip = self.condition()?;
+                let word = self.next("then or begin")?;
+                match word.text {
+                    "then" => self.emit(skip)?,
+                    "begin" => {
+                        self.emit(negate(skip))?;
+                        self.blocks.push((Block::If { jump: self.here }, tok));
+                        self.emit(Jump(0))?;
+                    },
+                    _ => return word.error("expected then or begin"),
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some((Block::If { jump }, open)) => {
+                    self.blocks.push((Block::Else { jump: self.here }, open));
+                    self.emit(Jump(0))?;
+                    self.patch(jump, self.here, tok)?;
+                },
+                _ => return tok.error("else without begin"),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If { jump }, _)) | Some((Block::Else { jump }, _)) => self.patch(jump, self.here, tok)?,
+                _ => return tok.error("end without begin"),
+            },
+            "loop" => self.blocks.push((Block::Loop { start: self.here, breaks: vec![] }, tok)),
+            "while" => {
+                let skip = self.condition()?;
+                let jump = match self.here.checked_add(2) {
+                    Some(jump) => jump,
+                    None => return tok.error("past the end of the address space"),
+                };
+                match self.blocks.iter_mut().rev().find(|b| matches!(b.0, Block::Loop { .. })) {
+                    Some(&mut (Block::Loop { ref mut breaks, .. }, _)) => breaks.push(jump),
+                    _ => return tok.error("while without loop"),
+                }
+                self.emit(negate(skip))?;
+                self.emit(Jump(0))?;
+            },
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop { start, breaks }, _)) => {
+                    if start > 0xFFF {
+                        return tok.error(format!("{:#X} does not fit into an address", start));
+                    }
+                    self.emit(Jump(start))?;
+                    for jump in breaks {
+                        self.patch(jump, self.here, tok)?;
+                    }
+                },
+                _ => return tok.error("again without loop"),
+            },
+            text => if let Some(x) = self.register(text) {
+                self.assignment(x)?;
+            } else if self.macros.contains_key(text) {
+                self.expand(tok)?;
+            } else if let Some(n) = number(text) {
+                if !(-128..=0xFF).contains(&n) {
+                    return tok.error(format!("{:#X} does not fit into a byte", n));
+                }
+                self.byte(n as u8)?;
+            } else if text.starts_with(':') {
+                return tok.error(format!("unknown directive {}", text));
+            } else {
+                // A bare name calls a subroutine.
+                self.tokens.push_front(tok);
+                self.emit_addr(Call(0))?;
+            },
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<()> {
+        let op = self.next("an operator")?;
+        let inst = match op.text {
+            ":=" => match self.peek() {
+                Some("random") => { self.next("random")?; Random { x, nn: self.nn()? } },
+                Some("delay") => { self.next("delay")?; GetDelay { x } },
+                Some("key") => { self.next("key")?; WaitKey { x } },
+                Some(text) if self.register(text).is_some() => Move { x, y: self.reg()? },
+                _ => LoadImm { x, nn: self.nn()? },
+            },
+            "+=" => match self.peek() {
+                Some(text) if self.register(text).is_some() => Add { x, y: self.reg()? },
+                _ => AddImm { x, nn: self.nn()? },
+            },
+            "-=" => match self.peek() {
+                Some(text) if self.register(text).is_some() => Sub { x, y: self.reg()? },
+                _ => AddImm { x, nn: self.nn()?.wrapping_neg() },
+            },
+            "=-" => SubN { x, y: self.reg()? },
+            "|=" => Or { x, y: self.reg()? },
+            "&=" => And { x, y: self.reg()? },
+            "^=" => Xor { x, y: self.reg()? },
+            ">>=" => ShiftRight { x, y: self.reg()? },
+            "<<=" => ShiftLeft { x, y: self.reg()? },
+            _ => return op.error(format!("unknown operator {}", op.text)),
+        };
+        self.emit(inst)?;
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<()> {
+        let op = self.next("an operator")?;
+        match op.text {
+            ":=" => match self.peek() {
+                Some("long") => {
+                    self.next("long")?;
+                    self.emit(LoadLongI)?;
+                    let tok = self.next("an address")?;
+                    self.fixups.push((self.here, Width::Long, tok));
+                    self.word(0)?;
+                },
+                Some("hex") => { self.next("hex")?; let x = self.reg()?; self.emit(Font { x })? },
+                Some("bighex") => { self.next("bighex")?; let x = self.reg()?; self.emit(BigFont { x })? },
+                _ => self.emit_addr(LoadI(0))?,
+            },
+            "+=" => { let x = self.reg()?; self.emit(AddI { x })? },
+            _ => return op.error(format!("unknown operator {}", op.text)),
+        }
+        Ok(())
+    }
+
+    /// The skip instruction making the next one run only if the
+    /// condition holds.
+    fn condition(&mut self) -> Result<Instruction> {
+        let x = self.reg()?;
+        let op = self.next("a comparison")?;
+        let y = match op.text {
+            "key" => return Ok(SkipNotKey { x }),
+            "-key" => return Ok(SkipKey { x }),
+            "==" | "!=" => self.peek().and_then(|text| self.register(text)),
+            _ => return op.error(format!("unknown comparison {}", op.text)),
+        };
+        Ok(match (op.text, y) {
+            ("==", Some(_)) => SkipNe { x, y: self.reg()? },
+            ("!=", Some(_)) => SkipEq { x, y: self.reg()? },
+            ("==", None) => SkipNeImm { x, nn: self.nn()? },
+            _ => SkipEqImm { x, nn: self.nn()? },
+        })
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.next("a name")?;
+        let name = self.define(name)?;
+        let mut args = vec![];
+        loop {
+            let tok = self.next("{")?;
+            if tok.text == "{" { break }
+            args.push(tok.text);
+        }
+        let mut body = vec![];
+        let mut depth = 0;
+        loop {
+            let tok = self.next("}")?;
+            match tok.text {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(tok);
+        }
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, tok: Token<'a>) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return tok.error("macro expansion too deep");
+        }
+        let mut values = HashMap::new();
+        for i in 0..self.macros[tok.text].args.len() {
+            let arg = self.next("a macro argument")?;
+            values.insert(self.macros[tok.text].args[i], arg);
+        }
+        let body: Vec<Token> = self.macros[tok.text].body.iter()
+            .map(|t| values.get(t.text).cloned().unwrap_or(*t))
+            .collect();
+        for t in body.into_iter().rev() {
+            self.tokens.push_front(t);
+        }
+        Ok(())
+    }
+
+    /// Point the jump at `addr` to `target`, where `tok` ends its block.
+    fn patch(&mut self, addr: u16, target: u16, tok: Token) -> Result<()> {
+        let i = Assembler::offset(addr, tok)?;
+        Assembler::offset(target, tok)?;
+        if target > 0xFFF {
+            return tok.error(format!("{:#X} does not fit into an address", target));
+        }
+        self.rom[i] = self.rom[i] & 0xF0 | (target >> 8) as u8;
+        self.rom[i + 1] = target as u8;
+        Ok(())
+    }
+
+    fn resolve(&mut self) -> Result<()> {
+        for &(addr, ref width, tok) in &self.fixups {
+            let value = match number(tok.text)
+                .or_else(|| self.consts.get(tok.text).cloned())
+                .or_else(|| self.labels.get(tok.text).map(|&a| a as i32)) {
+                Some(value) => value,
+                None => return tok.error(format!("undefined name {}", tok.text)),
+            };
+            let i = Assembler::offset(addr, tok)?;
+            match *width {
+                Width::Addr if !(0..=0xFFF).contains(&value) =>
+                    return tok.error(format!("{:#X} does not fit into an address", value)),
+                Width::Addr => {
+                    self.rom[i] |= (value >> 8) as u8;
+                    self.rom[i + 1] = value as u8;
+                },
+                Width::Long if !(0..=0xFFFF).contains(&value) =>
+                    return tok.error(format!("{:#X} does not fit into a word", value)),
+                Width::Long => {
+                    self.rom[i] = (value >> 8) as u8;
+                    self.rom[i + 1] = value as u8;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The skip instruction with the opposite condition.
+fn negate(skip: Instruction) -> Instruction {
+    match skip {
+        SkipEqImm { x, nn } => SkipNeImm { x, nn },
+        SkipNeImm { x, nn } => SkipEqImm { x, nn },
+        SkipEq { x, y } => SkipNe { x, y },
+        SkipNe { x, y } => SkipEq { x, y },
+        SkipKey { x } => SkipNotKey { x },
+        SkipNotKey { x } => SkipKey { x },
+        inst => inst,
+    }
+}
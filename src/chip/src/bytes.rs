@@ -0,0 +1,65 @@
+//! Big-endian numbers in the binary formats, like save states and
+//! movies.
+
+/// Append the low `n` bytes of `v`, most significant first.
+pub fn push(out: &mut Vec<u8>, v: u64, n: usize) {
+    out.extend((0..n).rev().map(|i| (v >> (8 * i)) as u8));
+}
+
+/// Reads front to back, failing with `err` when cut short.
+pub struct Reader<'a, E> {
+    data: &'a [u8],
+    err: E,
+}
+
+impl<'a, E: Copy> Reader<'a, E> {
+    pub fn new(data: &'a [u8], err: E) -> Reader<'a, E> {
+        Reader { data, err }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], E> {
+        if self.data.len() < len { return Err(self.err) }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// An `n` bytes number.
+    pub fn number(&mut self, n: usize) -> Result<u64, E> {
+        self.bytes(n).map(|b| b.iter().fold(0, |v, &b| v << 8 | b as u64))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, E> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, E> {
+        self.number(2).map(|v| v as u16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, E> {
+        self.number(4).map(|v| v as u32)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, E> {
+        self.number(8)
+    }
+
+    /// One byte of `0` or `1`.
+    pub fn bool(&mut self) -> Result<bool, E> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.err),
+        }
+    }
+
+    pub fn array(&mut self, out: &mut [u8]) -> Result<(), E> {
+        out.copy_from_slice(self.bytes(out.len())?);
+        Ok(())
+    }
+}
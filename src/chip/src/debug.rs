@@ -0,0 +1,368 @@
+//! Debugger running a `Chip` frame by frame, pausing on breakpoints
+//! and stepping through instructions.
+
+use std::collections::{BTreeSet, VecDeque};
+use instruction::{self, Instruction};
+use peripheral::{Timer, Video, Audio, Input, Machine};
+use watch::{Action, Hit};
+use {Chip, Flow, Result, Status};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test::Mock;
+    use watch::Watchpoint;
+    use rewind::Rewind;
+
+    /// 200: call 206, v1 += 1, jump 202
+    /// 206: v0 += 1, return
+    const PROGRAM: &[u8] = &[0x22, 0x06, 0x71, 0x01, 0x12, 0x02, 0x70, 0x01, 0x00, 0xEE];
+
+    fn setup() -> (Chip, Debugger, Mock) {
+        let mut chip = Chip::default();
+        chip.load(0x200, PROGRAM);
+        (chip, Debugger::default(), Mock::default())
+    }
+
+    #[test]
+    fn breakpoint() {
+        let (mut chip, mut dbg, mut p) = setup();
+        dbg.toggle_breakpoint(0x206);
+        dbg.frame(&mut chip, 100, &mut p).unwrap();
+        assert!(dbg.is_paused());
+        assert_eq!(chip.pc(), 0x206);
+
+        // Paused frames run nothing.
+        dbg.frame(&mut chip, 100, &mut p).unwrap();
+        assert_eq!(chip.pc(), 0x206);
+
+        // Resuming runs the instruction under the breakpoint first.
+        dbg.resume();
+        dbg.frame(&mut chip, 4, &mut p).unwrap();
+        assert!(!dbg.is_paused());
+        assert_eq!(chip.regs()[0], 1);
+
+        dbg.toggle_breakpoint(0x206);
+        assert!(dbg.breakpoints().next().is_none());
+    }
+
+    #[test]
+    fn breakpoint_at_wait_key() {
+        let mut chip = Chip::default();
+        chip.load(0x200, &[0xF0, 0x0A, 0x12, 0x00]);
+        let mut dbg = Debugger::default();
+        let mut p = Mock::default();
+        dbg.toggle_breakpoint(0x200);
+        dbg.frame(&mut chip, 10, &mut p).unwrap();
+        assert!(dbg.is_paused());
+
+        // Not paused again by the wait running again in later frames.
+        dbg.resume();
+        for _ in 0..3 {
+            dbg.frame(&mut chip, 10, &mut p).unwrap();
+            assert!(!dbg.is_paused());
+            assert!(chip.waiting_for_key());
+        }
+    }
+
+    #[test]
+    fn step() {
+        let (mut chip, mut dbg, mut p) = setup();
+        dbg.pause();
+        assert!(!dbg.step(&mut chip, &mut p).unwrap());
+        assert_eq!(chip.pc(), 0x206);
+        assert!(dbg.is_paused());
+    }
+
+    #[test]
+    fn step_over() {
+        let (mut chip, mut dbg, mut p) = setup();
+        dbg.pause();
+        dbg.step_over(&mut chip, &mut p).unwrap();
+        dbg.frame(&mut chip, 100, &mut p).unwrap();
+        assert!(dbg.is_paused());
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.regs()[0], 1);
+
+        // Not a call: a single step.
+        dbg.step_over(&mut chip, &mut p).unwrap();
+        assert_eq!(chip.pc(), 0x204);
+    }
+
+    #[test]
+    fn step_out() {
+        let (mut chip, mut dbg, mut p) = setup();
+        dbg.pause();
+        dbg.step(&mut chip, &mut p).unwrap();
+        dbg.step_out(&mut chip, &mut p).unwrap();
+        dbg.frame(&mut chip, 100, &mut p).unwrap();
+        assert!(dbg.is_paused());
+        assert_eq!(chip.pc(), 0x202);
+        assert!(chip.stack().is_empty());
+
+        // Outside of any subroutine, a plain step.
+        dbg.step_out(&mut chip, &mut p).unwrap();
+        assert!(dbg.is_paused());
+        assert_eq!(chip.pc(), 0x204);
+    }
+
+    #[test]
+    fn run_to() {
+        let (mut chip, mut dbg, mut p) = setup();
+        dbg.pause();
+        dbg.run_to(0x204);
+        dbg.frame(&mut chip, 100, &mut p).unwrap();
+        assert!(dbg.is_paused());
+        assert_eq!(chip.pc(), 0x204);
+        assert_eq!(chip.regs()[1], 1);
+    }
+
+    #[test]
+    fn replay() {
+        let (mut chip, mut dbg, mut p) = setup();
+        let mut rewind = Rewind::new(10);
+        rewind.push(dbg.cycles(), chip.save_state(0, &[]));
+        dbg.frame(&mut chip, 4, &mut p).unwrap();
+        assert_eq!((dbg.cycles(), chip.regs()[1]), (4, 1));
+
+        // Back to before v1 += 1.
+        let (at, state) = rewind.seek(dbg.cycles() - 1).unwrap();
+        chip.load_state(state, 0).unwrap();
+        dbg.replay(&mut chip, &mut p, at, 3).unwrap();
+        assert!(dbg.is_paused());
+        assert_eq!((dbg.cycles(), chip.pc(), chip.regs()[0], chip.regs()[1]), (3, 0x202, 1, 0));
+    }
+
+    #[test]
+    fn replay_timers() {
+        // v0 := 10, delay := v0, v1 := delay, v2 := 1, loop.
+        let mut chip = Chip::default();
+        chip.load(0x200, &[0x60, 0x0A, 0xF0, 0x15, 0xF1, 0x07, 0x62, 0x01, 0x12, 0x08]);
+        let (mut dbg, mut p) = (Debugger::default(), Mock::default());
+        let mut rewind = Rewind::new(10);
+        for &cycles in &[2, 1] {
+            rewind.push(dbg.cycles(), chip.save_state(0, &[]));
+            dbg.frame(&mut chip, cycles, &mut p).unwrap();
+        }
+        let forward = (chip.regs(), chip.timers());
+        assert_eq!(forward.0[1], 9);
+        dbg.pause();
+        dbg.step(&mut chip, &mut p).unwrap();
+
+        // Back across v1 := delay, the timers as when it ran.
+        let (at, state) = rewind.seek(dbg.cycles() - 1).unwrap();
+        chip.load_state(state, 0).unwrap();
+        dbg.replay(&mut chip, &mut p, at, 3).unwrap();
+        assert_eq!((chip.regs(), chip.timers()), forward);
+    }
+
+    #[test]
+    fn watchpoint() {
+        let (mut chip, mut dbg, mut p) = setup();
+        let mut log = Watchpoint::new(0x206, 0x207);
+        log.action = Action::Log;
+        chip.add_watchpoint(log);
+        chip.add_watchpoint(Watchpoint::new(0x208, 0x208));
+        dbg.frame(&mut chip, 100, &mut p).unwrap();
+        assert!(dbg.is_paused());
+        // After the instruction at 0x208.
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(dbg.hits().map(|h| (h.pc, h.addr, h.action)).collect::<Vec<_>>(),
+                   vec![(0x206, 0x206, Action::Log), (0x206, 0x207, Action::Log), (0x208, 0x208, Action::Break)]);
+    }
+}
+
+/// Where to pause, besides breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// Back from the subroutine called at the current level.
+    Over { ret: u16, depth: usize },
+    /// Returned from the current subroutine.
+    Out { depth: usize },
+    Cursor(u16),
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    paused: bool,
+    target: Option<Target>,
+    /// Don't break before the next instruction, which we paused at.
+    resumed: bool,
+    /// Latest watchpoint hits.
+    hits: VecDeque<Hit>,
+    /// Instructions executed.
+    cycles: u64,
+}
+
+/// Watchpoint hits kept by the debugger.
+const HITS: usize = 16;
+
+impl Debugger {
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.target = None;
+    }
+
+    /// Continue running, until a breakpoint.
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.resumed = true;
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().cloned()
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    /// Instructions executed so far, to time snapshots by.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// After restoring a snapshot taken after `at` instructions.
+    pub fn set_cycles(&mut self, at: u64) {
+        self.cycles = at;
+    }
+
+    /// Continue from a snapshot taken after `at` instructions, at the
+    /// start of a frame before its timers count down, paused once
+    /// executed again up to `target`, e.g. to step back from
+    /// `target + 1`. Returns `Ok(true)` if the program quit.
+    pub fn replay<P>(&mut self, chip: &mut Chip, p: &mut P, at: u64, target: u64) -> Result<bool>
+        where P: Video + Audio + Input + Machine
+    {
+        self.set_cycles(at);
+        self.pause();
+        chip.tick(p);
+        while self.cycles < target {
+            if self.step(chip, p)? { return Ok(true) }
+        }
+        Ok(false)
+    }
+
+    /// Latest watchpoint hits, oldest first.
+    pub fn hits(&self) -> impl Iterator<Item = &Hit> + '_ {
+        self.hits.iter()
+    }
+
+    /// Execute one instruction, staying paused.
+    /// Returns `Ok(true)` if the program quit.
+    pub fn step<P>(&mut self, chip: &mut Chip, p: &mut P) -> Result<bool>
+        where P: Video + Audio + Input + Machine
+    {
+        self.pause();
+        let flow = chip.cycle(p)?;
+        self.cycles += 1;
+        self.take_hits(chip);
+        Ok(flow == Flow::Quit)
+    }
+
+    /// Step, running called subroutines through.
+    pub fn step_over<P>(&mut self, chip: &mut Chip, p: &mut P) -> Result<bool>
+        where P: Video + Audio + Input + Machine
+    {
+        match instruction::decode(chip.memory(chip.pc(), 2).iter().fold(0, |w, &b| w << 8 | b as u16)) {
+            Some(Instruction::Call(_)) => {
+                self.resume();
+                self.target = Some(Target::Over { ret: chip.pc().wrapping_add(2), depth: chip.stack().len() });
+                Ok(false)
+            },
+            _ => self.step(chip, p),
+        }
+    }
+
+    /// Run until the current subroutine returns, or step outside of any.
+    pub fn step_out<P>(&mut self, chip: &mut Chip, p: &mut P) -> Result<bool>
+        where P: Video + Audio + Input + Machine
+    {
+        match chip.stack().len() {
+            0 => self.step(chip, p),
+            depth => {
+                self.resume();
+                self.target = Some(Target::Out { depth });
+                Ok(false)
+            },
+        }
+    }
+
+    /// Run until `addr`.
+    pub fn run_to(&mut self, addr: u16) {
+        self.resume();
+        self.target = Some(Target::Cursor(addr));
+    }
+
+    /// Keep the hits of the last instruction.
+    /// Returns `true` if any of them breaks.
+    fn take_hits(&mut self, chip: &mut Chip) -> bool {
+        let mut broke = false;
+        for hit in chip.take_hits() {
+            broke |= hit.action == Action::Break;
+            if self.hits.len() == HITS {
+                self.hits.pop_front();
+            }
+            self.hits.push_back(hit);
+        }
+        broke
+    }
+
+    /// Not again while `FX0A` runs again waiting for a key.
+    fn should_break(&self, chip: &Chip) -> bool {
+        let pc = chip.pc();
+        let depth = chip.stack().len();
+        if chip.waiting_for_key() { return false }
+        self.breakpoints.contains(&pc) || match self.target {
+            Some(Target::Over { ret, depth: d }) => pc == ret && depth == d,
+            Some(Target::Out { depth: d }) => depth < d,
+            Some(Target::Cursor(addr)) => pc == addr,
+            None => false,
+        }
+    }
+
+    /// Like `Chip::frame`, pausing before an instruction at a breakpoint
+    /// or at the target of a step, and after an instruction hitting a
+    /// breaking watchpoint. While paused, only present the screen and
+    /// wait for the next frame.
+    pub fn frame<P>(&mut self, chip: &mut Chip, num_cycle: usize, p: &mut P) -> Result<Status>
+        where P: Timer + Video + Audio + Input + Machine
+    {
+        if !self.paused {
+            chip.tick(p);
+            for _ in 0..num_cycle {
+                if !self.resumed && self.should_break(chip) {
+                    self.pause();
+                    break;
+                }
+                self.resumed = false;
+                let flow = chip.cycle(p)?;
+                self.cycles += 1;
+                if self.take_hits(chip) {
+                    self.pause();
+                }
+                match flow {
+                    Flow::Quit => return Ok(Status::Exited),
+                    _ if self.paused => break,
+                    Flow::Next => (),
+                    Flow::Wait => break,
+                }
+            }
+        }
+        p.present(chip.screen());
+        Ok(if p.pump() { Status::Quit } else { Status::Running })
+    }
+}
@@ -0,0 +1,365 @@
+//! Disassembler producing labelled listings in Octo syntax.
+//!
+//! Code is found by following the flow of the program from its entry
+//! point, so that data embedded in the program is not mistaken for
+//! instructions. Jump and call targets become labels. Memory pointed to
+//! by `I` when a sprite is drawn is listed as bitmaps, and other memory
+//! `I` points to is listed as bytes.
+
+use instruction::{self, Instruction};
+use instruction::Instruction::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROGRAM: &[u8] = &[
+        0x22, 0x08,     // 200: call 208
+        0x30, 0x00,     //      if v0 != 0 then
+        0x12, 0x00,     //          jump 200
+        0x12, 0x06,     // 206: jump 206
+        0xA2, 0x10,     // 208: i := 210
+        0xD0, 0x12,     //      sprite v0 v0 2
+        0xA2, 0x12,     //      i := 212
+        0x00, 0xEE,     //      return
+        0x81, 0x42,     // 210: sprite data
+        0x01, 0x02,     // 212: other data
+        0x03,
+    ];
+
+    #[test]
+    fn labels() {
+        let listing = disassemble(PROGRAM, 0x200).to_string();
+        assert!(listing.contains(": main\n"), "{}", listing);
+        assert!(listing.contains(": sub_0208\n"), "{}", listing);
+        assert!(listing.contains(": L_0206\n"), "{}", listing);
+        assert!(listing.contains("\tsub_0208 "), "{}", listing);
+        assert!(listing.contains("\tjump main "), "{}", listing);
+        assert!(listing.contains("\tjump L_0206 "), "{}", listing);
+        assert!(listing.contains("\ti := sprite_0210 "), "{}", listing);
+        assert!(listing.contains("\ti := data_0212 "), "{}", listing);
+    }
+
+    #[test]
+    fn data() {
+        let listing = disassemble(PROGRAM, 0x200).to_string();
+        assert!(listing.contains("\t0x81\t# 0210  #......#\n"), "{}", listing);
+        assert!(listing.contains("\t0x42\t# 0211  .#....#.\n"), "{}", listing);
+        assert!(listing.contains("\t0x01 0x02 0x03\t# 0212\n"), "{}", listing);
+    }
+
+    #[test]
+    fn lines() {
+        let d = disassemble(PROGRAM, 0x200);
+        let addrs: Vec<u16> = d.lines.iter().map(|l| l.addr).collect();
+        assert_eq!(addrs, vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20C, 0x20E, 0x210, 0x211, 0x212]);
+        assert_eq!(d.lines[1].item, Item::Code(SkipEqImm { x: 0, nn: 0 }));
+    }
+
+    #[test]
+    fn long_i() {
+        let listing = disassemble(&[0xF0, 0x00, 0x02, 0x06, 0x00, 0xFD, 0x55], 0x200).to_string();
+        assert!(listing.contains("\ti := long data_0206 "), "{}", listing);
+        assert!(listing.contains("\texit "), "{}", listing);
+        assert!(listing.contains("\t0x55\t# 0206\n"), "{}", listing);
+    }
+
+    #[test]
+    fn unreachable_is_data() {
+        let listing = disassemble(&[0x12, 0x00, 0x60, 0x01], 0x200).to_string();
+        assert!(listing.contains("\t0x60 0x01\t# 0202\n"), "{}", listing);
+    }
+
+    #[test]
+    fn end_of_address_space() {
+        let d = disassemble(&[0; 0xFF00], 0x200);
+        assert_eq!(d.lines.last().map(|l| l.addr), Some(0xFFFE));
+    }
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(SkipNotKey { x: 3 }.to_string(), "if v3 key then");
+        assert_eq!(SubN { x: 0xA, y: 0xB }.to_string(), "va =- vb");
+        assert_eq!(Draw { x: 1, y: 2, n: 0 }.to_string(), "sprite v1 v2 0");
+        assert_eq!(LoadI(0x2A0).to_string(), "i := 0x2A0");
+        assert_eq!(Sys(0x123).to_string(), "0x01 0x23");
+    }
+}
+
+/// What a label points to, in order of precedence for naming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Data,
+    Sprite,
+    Jump,
+    Sub,
+    Entry,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Code(Instruction),
+    /// `F000 NNNN`, with the address.
+    LongI(u16),
+    /// One row of a sprite, 1 byte wide, or 2 for 16x16 sprites.
+    Sprite(Vec<u8>),
+    Data(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    /// Label of `addr`, if anything refers to it.
+    pub label: Option<String>,
+    pub item: Item,
+}
+
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    origin: u16,
+    labels: BTreeMap<u16, String>,
+}
+
+impl Disassembly {
+    /// Name of `addr`, a label or a number.
+    pub fn name(&self, addr: u16) -> String {
+        self.labels.get(&addr).cloned().unwrap_or_else(|| format!("0x{:03X}", addr))
+    }
+}
+
+/// Disassemble `rom` loaded at `origin`, which is also its entry point.
+/// Bytes past the end of the address space are left out.
+pub fn disassemble(rom: &[u8], origin: u16) -> Disassembly {
+    let rom = &rom[..rom.len().min(0x10000 - origin as usize)];
+    let a = analyze(rom, origin);
+
+    let labels: BTreeMap<u16, String> = a.targets.iter()
+        .filter(|&(&addr, _)| addr >= origin && ((addr - origin) as usize) < rom.len())
+        .map(|(&addr, &target)| (addr, label(addr, target)))
+        .collect();
+
+    let mut lines = vec![];
+    let mut i = 0;
+    while i < rom.len() {
+        let addr = origin + i as u16;
+        let (item, size) = match a.code[i] {
+            Some(LoadLongI) => (Item::LongI(word(rom, i + 2).unwrap_or(0)), 4),
+            Some(inst) => (Item::Code(inst), 2),
+            None => match a.sprites.get(&addr) {
+                Some(&width) => (Item::Sprite(rom[i..(i + width).min(rom.len())].to_vec()), width),
+                None => {
+                    // Bytes up to the next thing of interest, 8 at most.
+                    let len = (i..rom.len())
+                        .take(8)
+                        .take_while(|&j| j == i || (a.code[j].is_none() && !labels.contains_key(&(origin + j as u16))))
+                        .take_while(|&j| j == i || !a.sprites.contains_key(&(origin + j as u16)))
+                        .count();
+                    (Item::Data(rom[i..i + len].to_vec()), len)
+                },
+            },
+        };
+        lines.push(Line { addr, label: labels.get(&addr).cloned(), item });
+        i += size;
+    }
+
+    Disassembly { lines, origin, labels }
+}
+
+fn label(addr: u16, target: Target) -> String {
+    match target {
+        Target::Entry => "main".to_string(),
+        Target::Sub => format!("sub_{:04X}", addr),
+        Target::Jump => format!("L_{:04X}", addr),
+        Target::Sprite => format!("sprite_{:04X}", addr),
+        Target::Data => format!("data_{:04X}", addr),
+    }
+}
+
+fn word(rom: &[u8], i: usize) -> Option<u16> {
+    if i + 1 < rom.len() { Some((rom[i] as u16) << 8 | rom[i + 1] as u16) } else { None }
+}
+
+struct Analysis {
+    /// Instruction starting at each byte of the ROM.
+    code: Vec<Option<Instruction>>,
+    targets: BTreeMap<u16, Target>,
+    /// Start of each sprite row, with its width in bytes.
+    sprites: BTreeMap<u16, usize>,
+}
+
+impl Analysis {
+    fn refer(&mut self, addr: u16, target: Target) {
+        let t = self.targets.entry(addr).or_insert(target);
+        if *t < target { *t = target }
+    }
+}
+
+/// Follow every path through the program, tracking `I` where it is known.
+fn analyze(rom: &[u8], origin: u16) -> Analysis {
+    let mut a = Analysis {
+        code: vec![None; rom.len()],
+        targets: BTreeMap::new(),
+        sprites: BTreeMap::new(),
+    };
+    a.refer(origin, Target::Entry);
+
+    let offset = |addr: u16| addr.checked_sub(origin).map(|i| i as usize).filter(|&i| i < rom.len());
+    let size = |i: usize| if word(rom, i) == Some(0xF000) { 4 } else { 2 };
+
+    let mut visited = vec![false; rom.len()];
+    let mut todo = vec![(origin, None)];
+    while let Some((addr, mut reg_i)) = todo.pop() {
+        let mut addr = addr;
+        while let Some(i) = offset(addr) {
+            if visited[i] { break }
+            let inst = match word(rom, i).and_then(instruction::decode) {
+                Some(inst) => inst,
+                None => break,
+            };
+            visited[i] = true;
+            a.code[i] = Some(inst);
+            let next = addr.wrapping_add(size(i) as u16);
+
+            match inst {
+                Jump(target) => { a.refer(target, Target::Jump); todo.push((target, reg_i)); break }
+                JumpOffset(target) => { a.refer(target, Target::Jump); todo.push((target, reg_i)); break }
+                Call(target) => { a.refer(target, Target::Sub); todo.push((target, None)); reg_i = None }
+                Return | Exit => break,
+                SkipEqImm { .. } | SkipNeImm { .. } | SkipEq { .. } | SkipNe { .. } | SkipKey { .. } | SkipNotKey { .. } => {
+                    if let Some(j) = offset(next) {
+                        todo.push((next.wrapping_add(size(j) as u16), reg_i));
+                    }
+                }
+                LoadI(target) => { a.refer(target, Target::Data); reg_i = Some(target) }
+                LoadLongI => {
+                    let target = word(rom, i + 2).unwrap_or(0);
+                    a.refer(target, Target::Data);
+                    reg_i = Some(target);
+                }
+                Draw { n, .. } => if let Some(target) = reg_i {
+                    a.refer(target, Target::Sprite);
+                    let (rows, width) = if n == 0 { (16, 2) } else { (n as u16, 1) };
+                    for row in 0..rows {
+                        a.sprites.insert(target.wrapping_add(row * width as u16), width);
+                    }
+                },
+                AddI { .. } | Font { .. } | BigFont { .. } | Store { .. } | Load { .. } => reg_i = None,
+                _ => (),
+            }
+            addr = next;
+        }
+    }
+
+    // Code always wins over data overlapping it.
+    for (i, inst) in a.code.clone().into_iter().enumerate() {
+        if inst.is_some() {
+            for j in i..(i + size(i)).min(rom.len()) {
+                a.sprites.remove(&(origin + j as u16));
+            }
+        }
+    }
+    a
+}
+
+/// Format `inst` in Octo syntax, naming addresses with `name`.
+/// `F000 NNNN` is formatted without its address.
+pub fn mnemonic<F: Fn(u16) -> String>(inst: &Instruction, name: F) -> String {
+    match *inst {
+        ScrollDown(n) => format!("scroll-down {}", n),
+        ScrollUp(n) => format!("scroll-up {}", n),
+        Clear => "clear".to_string(),
+        Return => "return".to_string(),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        LoRes => "lores".to_string(),
+        HiRes => "hires".to_string(),
+        // Octo has no such thing, so keep the bytes.
+        Sys(nnn) => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+        Jump(nnn) => format!("jump {}", name(nnn)),
+        Call(nnn) => name(nnn),
+        SkipEqImm { x, nn } => format!("if v{:x} != 0x{:02X} then", x, nn),
+        SkipNeImm { x, nn } => format!("if v{:x} == 0x{:02X} then", x, nn),
+        SkipEq { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        LoadImm { x, nn } => format!("v{:x} := 0x{:02X}", x, nn),
+        AddImm { x, nn } => format!("v{:x} += 0x{:02X}", x, nn),
+        Move { x, y } => format!("v{:x} := v{:x}", x, y),
+        Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Add { x, y } => format!("v{:x} += v{:x}", x, y),
+        Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        SubN { x, y } => format!("v{:x} =- v{:x}", x, y),
+        ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        SkipNe { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        LoadI(nnn) => format!("i := {}", name(nnn)),
+        JumpOffset(nnn) => format!("jump0 {}", name(nnn)),
+        Random { x, nn } => format!("v{:x} := random 0x{:02X}", x, nn),
+        Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        SkipKey { x } => format!("if v{:x} -key then", x),
+        SkipNotKey { x } => format!("if v{:x} key then", x),
+        LoadLongI => "i := long".to_string(),
+        Planes(n) => format!("plane {}", n),
+        Audio => "audio".to_string(),
+        GetDelay { x } => format!("v{:x} := delay", x),
+        WaitKey { x } => format!("v{:x} := key", x),
+        SetDelay { x } => format!("delay := v{:x}", x),
+        SetSound { x } => format!("buzzer := v{:x}", x),
+        AddI { x } => format!("i += v{:x}", x),
+        Font { x } => format!("i := hex v{:x}", x),
+        BigFont { x } => format!("i := bighex v{:x}", x),
+        Bcd { x } => format!("bcd v{:x}", x),
+        Pitch { x } => format!("pitch := v{:x}", x),
+        Store { x } => format!("save v{:x}", x),
+        Load { x } => format!("load v{:x}", x),
+        SaveFlags { x } => format!("saveflags v{:x}", x),
+        LoadFlags { x } => format!("loadflags v{:x}", x),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&mnemonic(self, |addr| format!("0x{:03X}", addr)))
+    }
+}
+
+fn bitmap(row: &[u8]) -> String {
+    row.iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| if byte & (1 << i) != 0 { '#' } else { '.' }))
+        .collect()
+}
+
+fn bytes(data: &[u8]) -> String {
+    data.iter().map(|b| format!("0x{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.origin != 0x200 {
+            writeln!(f, ":org 0x{:03X}", self.origin)?;
+        }
+        for line in &self.lines {
+            if let Some(ref label) = line.label {
+                writeln!(f, "\n: {}", label)?;
+            }
+            match line.item {
+                Item::Code(ref inst) => {
+                    let text = mnemonic(inst, |addr| self.name(addr));
+                    writeln!(f, "\t{:<24}# {:04X}  {:04X}", text, line.addr, instruction::encode(inst))?
+                },
+                Item::LongI(addr) => {
+                    let text = format!("i := long {}", self.name(addr));
+                    writeln!(f, "\t{:<24}# {:04X}  F000 {:04X}", text, line.addr, addr)?
+                },
+                Item::Sprite(ref row) => writeln!(f, "\t{}\t# {:04X}  {}", bytes(row), line.addr, bitmap(row))?,
+                Item::Data(ref data) => writeln!(f, "\t{}\t# {:04X}", bytes(data), line.addr)?,
+            }
+        }
+        Ok(())
+    }
+}
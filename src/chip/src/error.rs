@@ -0,0 +1,52 @@
+//! Errors stopping the execution of a program.
+//!
+//! These are faults of the running program, not of the emulator, so the
+//! host can report them instead of crashing.
+
+use std::error;
+use std::fmt;
+use std::result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// `inst` fetched from `addr` is not a known instruction.
+    UnknownInstruction { addr: u16, inst: u16 },
+    /// `00EE` at `addr` with nothing to return to.
+    StackUnderflow { addr: u16 },
+    /// `2NNN` at `addr` with every stack level in use.
+    StackOverflow { addr: u16 },
+    /// The instruction at `addr` accessed `len` bytes from `start`,
+    /// beyond the end of RAM.
+    OutOfRange { addr: u16, start: usize, len: usize },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+    /// Address of the faulting instruction.
+    pub fn addr(&self) -> u16 {
+        match *self {
+            Error::UnknownInstruction { addr, .. } => addr,
+            Error::StackUnderflow { addr } => addr,
+            Error::StackOverflow { addr } => addr,
+            Error::OutOfRange { addr, .. } => addr,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownInstruction { addr, inst } =>
+                write!(f, "{:04X}: unknown instruction {:04X}", addr, inst),
+            Error::StackUnderflow { addr } =>
+                write!(f, "{:04X}: return with an empty stack", addr),
+            Error::StackOverflow { addr } =>
+                write!(f, "{:04X}: call with a full stack", addr),
+            Error::OutOfRange { addr, start, len } =>
+                write!(f, "{:04X}: access to {} byte(s) at {:04X} is out of range", addr, len, start),
+        }
+    }
+}
+
+impl error::Error for Error {}
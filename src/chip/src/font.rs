@@ -0,0 +1,104 @@
+//! Built-in hexadecimal fonts.
+//!
+//! The glyphs live in the interpreter area (below `0x0200`) so that programs
+//! can point `I` at them with `FX29`, or `FX30` for the large ones.
+
+/// Number of bytes (rows) in one glyph.
+pub const GLYPH_SIZE: usize = 5;
+
+/// Sixteen 4x5 glyphs, `0` ~ `F`, one byte per row, left aligned.
+pub type Glyphs = [[u8; GLYPH_SIZE]; 16];
+
+/// The glyphs found in the original COSMAC VIP interpreter.
+pub const GLYPHS: Glyphs = [
+    [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
+    [0x20, 0x60, 0x20, 0x20, 0x70], // 1
+    [0xF0, 0x10, 0xF0, 0x80, 0xF0], // 2
+    [0xF0, 0x10, 0xF0, 0x10, 0xF0], // 3
+    [0x90, 0x90, 0xF0, 0x10, 0x10], // 4
+    [0xF0, 0x80, 0xF0, 0x10, 0xF0], // 5
+    [0xF0, 0x80, 0xF0, 0x90, 0xF0], // 6
+    [0xF0, 0x10, 0x20, 0x40, 0x40], // 7
+    [0xF0, 0x90, 0xF0, 0x90, 0xF0], // 8
+    [0xF0, 0x90, 0xF0, 0x10, 0xF0], // 9
+    [0xF0, 0x90, 0xF0, 0x90, 0x90], // A
+    [0xE0, 0x90, 0xE0, 0x90, 0xE0], // B
+    [0xF0, 0x80, 0x80, 0x80, 0xF0], // C
+    [0xE0, 0x90, 0x90, 0x90, 0xE0], // D
+    [0xF0, 0x80, 0xF0, 0x80, 0xF0], // E
+    [0xF0, 0x80, 0xF0, 0x80, 0x80], // F
+];
+
+/// Number of bytes (rows) in one large glyph.
+pub const BIG_GLYPH_SIZE: usize = 10;
+
+/// Sixteen 8x10 glyphs, `0` ~ `F`, one byte per row.
+pub type BigGlyphs = [[u8; BIG_GLYPH_SIZE]; 16];
+
+/// The large digits of SUPER-CHIP 1.1, completed with `A` ~ `F`.
+pub const BIG_GLYPHS: BigGlyphs = [
+    [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C], // 0
+    [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C], // 1
+    [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF], // 2
+    [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C], // 3
+    [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06], // 4
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C], // 5
+    [0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C], // 6
+    [0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60], // 7
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C], // 8
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C], // 9
+    [0x3C, 0x7E, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3], // A
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC], // B
+    [0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C], // C
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC], // D
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF], // E
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0], // F
+];
+
+/// Where the built-in glyphs are placed by default.
+pub const FONT_ADDR: u16 = 0x0050;
+/// Right after the small glyphs.
+pub const BIG_FONT_ADDR: u16 = FONT_ADDR + 16 * GLYPH_SIZE as u16;
+
+#[derive(Clone, Copy)]
+pub struct Font {
+    /// Base address of glyph `0`; the others follow contiguously.
+    pub addr: u16,
+    pub glyphs: Glyphs,
+    /// Base address of large glyph `0`.
+    pub big_addr: u16,
+    pub big_glyphs: BigGlyphs,
+}
+
+impl Font {
+    /// Address of the glyph for the low nibble of `digit`.
+    pub fn glyph_addr(&self, digit: u8) -> u16 {
+        self.addr.wrapping_add((digit & 0xF) as u16 * GLYPH_SIZE as u16)
+    }
+
+    /// Address of the large glyph for the low nibble of `digit`.
+    pub fn big_glyph_addr(&self, digit: u8) -> u16 {
+        self.big_addr.wrapping_add((digit & 0xF) as u16 * BIG_GLYPH_SIZE as u16)
+    }
+
+    /// The glyphs laid out as they are in RAM.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.glyphs.iter().flat_map(|g| g.iter().cloned()).collect()
+    }
+
+    /// The large glyphs laid out as they are in RAM.
+    pub fn big_bytes(&self) -> Vec<u8> {
+        self.big_glyphs.iter().flat_map(|g| g.iter().cloned()).collect()
+    }
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font {
+            addr: FONT_ADDR,
+            glyphs: GLYPHS,
+            big_addr: BIG_FONT_ADDR,
+            big_glyphs: BIG_GLYPHS,
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! The screen: pixels of up to `PLANES` XO-CHIP bitplanes, drawn to by
+//! the chip and handed to `Video::present` each frame.
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rows(fb: &Framebuffer) -> Vec<String> {
+        fb.pixels().chunks(fb.width()).map(|row| row.iter().map(|px| px.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn draw() {
+        let mut fb = Framebuffer::new(8, 2);
+        assert!(!fb.draw(1, 6, 0, &[0b1110_0000], false));
+        assert_eq!(rows(&fb), ["10000011", "00000000"], "wrapped around");
+        assert!(fb.draw(1, 7, 0, &[0b1000_0000, 0b1000_0000], false));
+        assert_eq!(rows(&fb), ["10000010", "00000001"]);
+
+        assert!(!fb.draw(2, 6, 0, &[0b1111_0000, 0b1111_0000], true));
+        assert_eq!(rows(&fb), ["10000032", "00000023"], "clipped");
+        fb.clear(1);
+        assert_eq!(rows(&fb), ["00000022", "00000022"], "only the given planes are cleared");
+    }
+
+    #[test]
+    fn scroll() {
+        let mut fb = Framebuffer::new(4, 2);
+        fb.draw(1, 0, 0, &[0b1000_0000, 0b0100_0000], false);
+        fb.draw(2, 0, 0, &[0b1000_0000], false);
+        fb.scroll(1, 2, 0);
+        assert_eq!(rows(&fb), ["2010", "0001"], "only the given planes move");
+        fb.scroll(3, 0, -1);
+        assert_eq!(rows(&fb), ["0001", "0000"]);
+    }
+
+    #[test]
+    fn from_pixels() {
+        let fb = Framebuffer::from_pixels(2, 1, vec![3, 0]).unwrap();
+        assert_eq!(fb.pixel(0, 0), 3);
+        assert_eq!(Framebuffer::from_pixels(2, 2, vec![3, 0]), None);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    w: usize,
+    h: usize,
+    /// The planes of each pixel, bit 0 for the first plane, row by row.
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    /// A `w`x`h` pixels screen, cleared.
+    pub fn new(w: usize, h: usize) -> Framebuffer {
+        Framebuffer { w, h, pixels: vec![0; w * h] }
+    }
+
+    /// A `w`x`h` pixels screen with the planes of each pixel in `pixels`,
+    /// row by row, unless it is not `w * h` long.
+    pub fn from_pixels(w: usize, h: usize, pixels: Vec<u8>) -> Option<Framebuffer> {
+        if pixels.len() != w * h { return None }
+        Some(Framebuffer { w, h, pixels })
+    }
+
+    pub fn width(&self) -> usize {
+        self.w
+    }
+
+    pub fn height(&self) -> usize {
+        self.h
+    }
+
+    /// The planes of the pixel at (`x`, `y`), bit 0 for the first plane.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[self.w * y + x]
+    }
+
+    /// The planes of every pixel, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Turn the `planes` off.
+    pub fn clear(&mut self, planes: u8) {
+        for px in self.pixels.iter_mut() {
+            *px &= !planes;
+        }
+    }
+
+    /// Flip the `planes` where the bits of the 8 pixels wide sprite `rows`
+    /// are set, from (`x`, `y`) on. The sprite is clipped at the edges if
+    /// `clip`, wrapped around if not.
+    /// Returns `true` if any of the `planes` has been flipped off.
+    pub fn draw(&mut self, planes: u8, x: usize, y: usize, rows: &[u8], clip: bool) -> bool {
+        let mut collided = false;
+        for (y, &row) in (y..).zip(rows) {
+            if clip && y >= self.h { break }
+            for (x, bit) in (x..).zip((0..8).rev()) {
+                if row & 1 << bit == 0 || clip && x >= self.w { continue }
+                let px = &mut self.pixels[self.w * (y % self.h) + x % self.w];
+                collided |= *px & planes != 0;
+                *px ^= planes;
+            }
+        }
+        collided
+    }
+
+    /// Switch to a `w`x`h` pixels screen, cleared.
+    pub fn resize(&mut self, w: usize, h: usize) {
+        *self = Framebuffer::new(w, h);
+    }
+
+    /// Move the `planes` by `dx` pixels right and `dy` pixels down.
+    /// Pixels moved in from outside are off.
+    pub fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let (w, h) = (self.w as isize, self.h as isize);
+        let old = self.pixels.clone();
+        for y in 0..h {
+            for x in 0..w {
+                let (ox, oy) = (x - dx, y - dy);
+                let inside = 0 <= ox && ox < w && 0 <= oy && oy < h;
+                let moved = if inside { old[(w * oy + ox) as usize] } else { 0 };
+                let px = &mut self.pixels[(w * y + x) as usize];
+                *px = *px & !planes | moved & planes;
+            }
+        }
+    }
+}
+
+impl Default for Framebuffer {
+    /// The low resolution screen.
+    fn default() -> Framebuffer {
+        Framebuffer::new(::LORES_WIDTH, ::LORES_HEIGHT)
+    }
+}
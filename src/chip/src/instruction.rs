@@ -0,0 +1,307 @@
+//! Instructions in a typed form, shared by the CPU and the tools.
+//!
+//! Every instruction is 16 bits, looked up nibble by nibble, except for
+//! `F000 NNNN`, whose address is in the word following it.
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::Instruction::*;
+
+    #[test]
+    fn round_trip() {
+        let mut valid = 0;
+        for op in 0..0x10000 {
+            let op = op as u16;
+            if let Some(inst) = decode(op) {
+                assert_eq!(encode(&inst), op, "{:04X} decoded as {:?}", op, inst);
+                valid += 1;
+            }
+        }
+        // Every opcode of 0NNN ~ 4NNN, 6NNN, 7NNN and ANNN ~ DNNN, only
+        // some of 5XYN, 8XYN, 9XYN, EXNN and FXNN.
+        assert_eq!(valid, 11 * 0x1000 + 3 * 0x100 + 9 * 0x100 + 0x100 + 2 * 0x10 + 2 + 14 * 0x10);
+    }
+
+    #[test]
+    fn inverse() {
+        let insts = [
+            ScrollDown(3), Clear, Return, Exit, HiRes, Sys(0x123), Jump(0xFFF),
+            SkipEqImm { x: 1, nn: 0xAB }, SaveRange { x: 2, y: 9 },
+            ShiftLeft { x: 0xF, y: 0 }, Draw { x: 3, y: 4, n: 0 },
+            LoadLongI, Planes(3), Audio, Pitch { x: 5 }, LoadFlags { x: 7 },
+        ];
+        for inst in insts.iter() {
+            assert_eq!(decode(encode(inst)), Some(*inst));
+        }
+    }
+
+    #[test]
+    fn unknown() {
+        for &op in &[0x5001, 0x800F, 0x9001, 0xE000, 0xF100 + 0xFF, 0xF102] {
+            assert_eq!(decode(op), None, "{:04X}", op);
+        }
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(encode(&Jump(0x1234)), 0x1234);
+        assert_eq!(encode(&AddImm { x: 0x1F, nn: 0x01 }), 0x7F01);
+    }
+}
+
+/// A decoded instruction, named after what it does.
+/// The comment of each variant is its pattern, where `X` and `Y` are
+/// registers, `N` is a nibble, `NN` a byte, and `NNN` an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `00CN`: scroll down by N pixels.
+    ScrollDown(u8),
+    /// `00DN`: scroll up by N pixels.
+    ScrollUp(u8),
+    /// `00E0`
+    Clear,
+    /// `00EE`
+    Return,
+    /// `00FB`: scroll right by 4 pixels.
+    ScrollRight,
+    /// `00FC`: scroll left by 4 pixels.
+    ScrollLeft,
+    /// `00FD`
+    Exit,
+    /// `00FE`
+    LoRes,
+    /// `00FF`
+    HiRes,
+    /// `0NNN`: call machine code.
+    Sys(u16),
+    /// `1NNN`
+    Jump(u16),
+    /// `2NNN`
+    Call(u16),
+    /// `3XNN`
+    SkipEqImm { x: u8, nn: u8 },
+    /// `4XNN`
+    SkipNeImm { x: u8, nn: u8 },
+    /// `5XY0`
+    SkipEq { x: u8, y: u8 },
+    /// `5XY2`: store VX ~ VY at I.
+    SaveRange { x: u8, y: u8 },
+    /// `5XY3`: load VX ~ VY from I.
+    LoadRange { x: u8, y: u8 },
+    /// `6XNN`
+    LoadImm { x: u8, nn: u8 },
+    /// `7XNN`
+    AddImm { x: u8, nn: u8 },
+    /// `8XY0`
+    Move { x: u8, y: u8 },
+    /// `8XY1`
+    Or { x: u8, y: u8 },
+    /// `8XY2`
+    And { x: u8, y: u8 },
+    /// `8XY3`
+    Xor { x: u8, y: u8 },
+    /// `8XY4`
+    Add { x: u8, y: u8 },
+    /// `8XY5`: VX -= VY
+    Sub { x: u8, y: u8 },
+    /// `8XY6`
+    ShiftRight { x: u8, y: u8 },
+    /// `8XY7`: VX = VY - VX
+    SubN { x: u8, y: u8 },
+    /// `8XYE`
+    ShiftLeft { x: u8, y: u8 },
+    /// `9XY0`
+    SkipNe { x: u8, y: u8 },
+    /// `ANNN`
+    LoadI(u16),
+    /// `BNNN`
+    JumpOffset(u16),
+    /// `CXNN`
+    Random { x: u8, nn: u8 },
+    /// `DXYN`: N rows, or 16x16 when N is 0.
+    Draw { x: u8, y: u8, n: u8 },
+    /// `EX9E`
+    SkipKey { x: u8 },
+    /// `EXA1`
+    SkipNotKey { x: u8 },
+    /// `F000 NNNN`: the address is in the next word.
+    LoadLongI,
+    /// `FN01`: select bitplanes N.
+    Planes(u8),
+    /// `F002`: load audio pattern from I.
+    Audio,
+    /// `FX07`
+    GetDelay { x: u8 },
+    /// `FX0A`
+    WaitKey { x: u8 },
+    /// `FX15`
+    SetDelay { x: u8 },
+    /// `FX18`
+    SetSound { x: u8 },
+    /// `FX1E`
+    AddI { x: u8 },
+    /// `FX29`
+    Font { x: u8 },
+    /// `FX30`
+    BigFont { x: u8 },
+    /// `FX33`
+    Bcd { x: u8 },
+    /// `FX3A`
+    Pitch { x: u8 },
+    /// `FX55`
+    Store { x: u8 },
+    /// `FX65`
+    Load { x: u8 },
+    /// `FX75`
+    SaveFlags { x: u8 },
+    /// `FX85`
+    LoadFlags { x: u8 },
+}
+
+/// Returns `None` for unknown instructions.
+/// Every instruction decoded is encoded back to the same opcode.
+pub fn decode(inst: u16) -> Option<Instruction> {
+    use self::Instruction::*;
+
+    let x = (inst >> 8 & 0xF) as u8;
+    let y = (inst >> 4 & 0xF) as u8;
+    let n = (inst & 0xF) as u8;
+    let nn = inst as u8;
+    let nnn = inst & 0xFFF;
+
+    Some(match inst >> 12 {
+        0x0 => match nnn {
+            0x0E0 => Clear,
+            0x0EE => Return,
+            0x0FB => ScrollRight,
+            0x0FC => ScrollLeft,
+            0x0FD => Exit,
+            0x0FE => LoRes,
+            0x0FF => HiRes,
+            _ if nnn >> 4 == 0x00C => ScrollDown(n),
+            _ if nnn >> 4 == 0x00D => ScrollUp(n),
+            _ => Sys(nnn),
+        },
+        0x1 => Jump(nnn),
+        0x2 => Call(nnn),
+        0x3 => SkipEqImm { x, nn },
+        0x4 => SkipNeImm { x, nn },
+        0x5 => match n {
+            0x0 => SkipEq { x, y },
+            0x2 => SaveRange { x, y },
+            0x3 => LoadRange { x, y },
+            _ => return None,
+        },
+        0x6 => LoadImm { x, nn },
+        0x7 => AddImm { x, nn },
+        0x8 => match n {
+            0x0 => Move { x, y },
+            0x1 => Or { x, y },
+            0x2 => And { x, y },
+            0x3 => Xor { x, y },
+            0x4 => Add { x, y },
+            0x5 => Sub { x, y },
+            0x6 => ShiftRight { x, y },
+            0x7 => SubN { x, y },
+            0xE => ShiftLeft { x, y },
+            _ => return None,
+        },
+        0x9 => match n {
+            0x0 => SkipNe { x, y },
+            _ => return None,
+        },
+        0xA => LoadI(nnn),
+        0xB => JumpOffset(nnn),
+        0xC => Random { x, nn },
+        0xD => Draw { x, y, n },
+        0xE => match nn {
+            0x9E => SkipKey { x },
+            0xA1 => SkipNotKey { x },
+            _ => return None,
+        },
+        _ => match nn {
+            0x00 if x == 0 => LoadLongI,
+            0x01 => Planes(x),
+            0x02 if x == 0 => Audio,
+            0x07 => GetDelay { x },
+            0x0A => WaitKey { x },
+            0x15 => SetDelay { x },
+            0x18 => SetSound { x },
+            0x1E => AddI { x },
+            0x29 => Font { x },
+            0x30 => BigFont { x },
+            0x33 => Bcd { x },
+            0x3A => Pitch { x },
+            0x55 => Store { x },
+            0x65 => Load { x },
+            0x75 => SaveFlags { x },
+            0x85 => LoadFlags { x },
+            _ => return None,
+        },
+    })
+}
+
+/// Fields wider than their place in the opcode are truncated.
+pub fn encode(inst: &Instruction) -> u16 {
+    use self::Instruction::*;
+
+    let xy = |op: u16, x: u8, y: u8, n: u16| op << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n;
+    let xnn = |op: u16, x: u8, nn: u8| op << 12 | (x as u16 & 0xF) << 8 | nn as u16;
+    let nnn = |op: u16, nnn: u16| op << 12 | nnn & 0xFFF;
+    let fx = |x: u8, nn: u8| xnn(0xF, x, nn);
+
+    match *inst {
+        ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+        ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+        Clear => 0x00E0,
+        Return => 0x00EE,
+        ScrollRight => 0x00FB,
+        ScrollLeft => 0x00FC,
+        Exit => 0x00FD,
+        LoRes => 0x00FE,
+        HiRes => 0x00FF,
+        Sys(a) => nnn(0x0, a),
+        Jump(a) => nnn(0x1, a),
+        Call(a) => nnn(0x2, a),
+        SkipEqImm { x, nn } => xnn(0x3, x, nn),
+        SkipNeImm { x, nn } => xnn(0x4, x, nn),
+        SkipEq { x, y } => xy(0x5, x, y, 0x0),
+        SaveRange { x, y } => xy(0x5, x, y, 0x2),
+        LoadRange { x, y } => xy(0x5, x, y, 0x3),
+        LoadImm { x, nn } => xnn(0x6, x, nn),
+        AddImm { x, nn } => xnn(0x7, x, nn),
+        Move { x, y } => xy(0x8, x, y, 0x0),
+        Or { x, y } => xy(0x8, x, y, 0x1),
+        And { x, y } => xy(0x8, x, y, 0x2),
+        Xor { x, y } => xy(0x8, x, y, 0x3),
+        Add { x, y } => xy(0x8, x, y, 0x4),
+        Sub { x, y } => xy(0x8, x, y, 0x5),
+        ShiftRight { x, y } => xy(0x8, x, y, 0x6),
+        SubN { x, y } => xy(0x8, x, y, 0x7),
+        ShiftLeft { x, y } => xy(0x8, x, y, 0xE),
+        SkipNe { x, y } => xy(0x9, x, y, 0x0),
+        LoadI(a) => nnn(0xA, a),
+        JumpOffset(a) => nnn(0xB, a),
+        Random { x, nn } => xnn(0xC, x, nn),
+        Draw { x, y, n } => xy(0xD, x, y, n as u16 & 0xF),
+        SkipKey { x } => xnn(0xE, x, 0x9E),
+        SkipNotKey { x } => xnn(0xE, x, 0xA1),
+        LoadLongI => 0xF000,
+        Planes(n) => fx(n, 0x01),
+        Audio => 0xF002,
+        GetDelay { x } => fx(x, 0x07),
+        WaitKey { x } => fx(x, 0x0A),
+        SetDelay { x } => fx(x, 0x15),
+        SetSound { x } => fx(x, 0x18),
+        AddI { x } => fx(x, 0x1E),
+        Font { x } => fx(x, 0x29),
+        BigFont { x } => fx(x, 0x30),
+        Bcd { x } => fx(x, 0x33),
+        Pitch { x } => fx(x, 0x3A),
+        Store { x } => fx(x, 0x55),
+        Load { x } => fx(x, 0x65),
+        SaveFlags { x } => fx(x, 0x75),
+        LoadFlags { x } => fx(x, 0x85),
+    }
+}
@@ -1,21 +1,677 @@
 //! `chip` implements the chip-8 CPU and RAM.
 //! Peripherals (IO) are decoupled with the chip and are handled by the user.
 
-extern crate rand;
+// Tests live at the top of each file.
+#![allow(clippy::items_after_test_module)]
 
-#[macro_use]
-mod decoder;
 mod ram;
+mod bytes;
 pub mod peripheral;
+pub mod framebuffer;
+pub mod font;
+pub mod error;
+pub mod quirks;
+pub mod instruction;
+pub mod disasm;
+pub mod asm;
+pub mod debug;
+pub mod watch;
+pub mod state;
+pub mod rewind;
+pub mod rng;
+pub mod movie;
 
-pub use peripheral::{Timer, Video, Audio, Input};
-use ram::{Ram, Write, Read, Slice};
+pub use peripheral::{Timer, Video, Audio, Input, Machine};
+pub use framebuffer::Framebuffer;
+pub use font::Font;
+pub use error::{Error, Result};
+pub use quirks::Quirks;
+pub use instruction::Instruction;
+pub use disasm::disassemble;
+pub use asm::assemble;
+pub use debug::Debugger;
+pub use watch::{Watchpoint, Hit};
+pub use state::rom_hash;
+pub use rewind::Rewind;
+pub use rng::{Rng, XorShift};
+pub use movie::Movie;
+pub use ram::RAM_SIZE;
+use ram::{Ram, Write, Slice};
 use std::num::Wrapping;
-use rand::random;
+use std::mem;
+use std::result;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    pub struct Mock {
+        pub keydowns: [bool; 16],
+        /// Keys down in the following frames, bit N for key N, taken one
+        /// by each `pump`.
+        pub script: Vec<u16>,
+        sys: Vec<u16>,
+        pattern: Option<([u8; 16], u8)>,
+    }
+
+    impl Timer for Mock {
+        fn pump(&mut self) -> bool {
+            if !self.script.is_empty() {
+                let keys = self.script.remove(0);
+                for (k, down) in self.keydowns.iter_mut().enumerate() {
+                    *down = keys & 1 << k != 0;
+                }
+            }
+            false
+        }
+    }
+
+    impl Video for Mock {
+        fn present(&mut self, _: &Framebuffer) {}
+    }
+
+    impl Audio for Mock {
+        fn buzz(&mut self, _: bool) {}
+        fn pattern(&mut self, pattern: &[u8; 16], pitch: u8) { self.pattern = Some((*pattern, pitch)) }
+    }
+
+    impl Input for Mock {
+        fn keydown(&self, which: usize) -> bool { self.keydowns[which] }
+    }
+
+    impl Machine for Mock {
+        fn sys(&mut self, addr: u16) { self.sys.push(addr) }
+    }
+
+    /// Load `program` at 0x0200 and execute `n` instructions of it.
+    fn run(chip: &mut Chip, p: &mut Mock, program: &[u8], n: usize) -> bool {
+        try_run(chip, p, program, n).unwrap()
+    }
+
+    fn try_run(chip: &mut Chip, p: &mut Mock, program: &[u8], n: usize) -> Result<bool> {
+        chip.load(0x200, program);
+        let mut stop = false;
+        for _ in 0..n {
+            stop = chip.cycle(p)? == Flow::Quit;
+        }
+        Ok(stop)
+    }
+
+    fn exec(program: &[u8], n: usize) -> Chip {
+        let mut chip = Chip::default();
+        run(&mut chip, &mut Default::default(), program, n);
+        chip
+    }
+
+    fn ram(chip: &mut Chip, addr: u16, len: usize) -> Vec<u8> {
+        chip.memory(addr, len).to_vec()
+    }
+
+    /// The 8 pixels from (`x`, `y`) on, as a sprite row of the bits of
+    /// `plane`.
+    fn row(chip: &Chip, plane: u8, x: usize, y: usize) -> u8 {
+        (0..8).fold(0, |row, i| row << 1 | (chip.screen.pixel(x + i, y) & plane != 0) as u8)
+    }
+
+    fn lit(chip: &Chip) -> usize {
+        chip.screen.pixels().iter().filter(|&&px| px != 0).count()
+    }
+
+    #[test]
+    fn clear() {
+        let mut chip = Chip::default();
+        chip.screen.draw(1, 0, 0, &[0xFF], false);
+        run(&mut chip, &mut Mock::default(), &[0x00, 0xE0], 1);
+        assert_eq!(lit(&chip), 0);
+    }
+
+    #[test]
+    fn call_and_return() {
+        let chip = exec(&[0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE], 1);
+        assert_eq!(chip.pc.0, 0x206);
+        assert_eq!(chip.stack, vec![0x202]);
+        let chip = exec(&[0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE], 2);
+        assert_eq!(chip.pc.0, 0x202);
+        assert!(chip.stack.is_empty());
+    }
+
+    #[test]
+    fn sys() {
+        let mut p = Mock::default();
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &[0x03, 0x45], 1);
+        assert_eq!(p.sys, vec![0x345]);
+        assert_eq!(chip.pc.0, 0x202);
+    }
+
+    #[test]
+    fn jump() {
+        assert_eq!(exec(&[0x1A, 0xBC], 1).pc.0, 0xABC);
+        assert_eq!(exec(&[0x60, 0x10, 0xB3, 0x00], 2).pc.0, 0x310);
+    }
+
+    #[test]
+    fn skip_imm() {
+        assert_eq!(exec(&[0x60, 0x12, 0x30, 0x12], 2).pc.0, 0x206);
+        assert_eq!(exec(&[0x60, 0x12, 0x30, 0x13], 2).pc.0, 0x204);
+        assert_eq!(exec(&[0x60, 0x12, 0x40, 0x12], 2).pc.0, 0x204);
+        assert_eq!(exec(&[0x60, 0x12, 0x40, 0x13], 2).pc.0, 0x206);
+    }
+
+    #[test]
+    fn skip_reg() {
+        assert_eq!(exec(&[0x60, 0x12, 0x61, 0x12, 0x50, 0x10], 3).pc.0, 0x208);
+        assert_eq!(exec(&[0x60, 0x12, 0x61, 0x13, 0x50, 0x10], 3).pc.0, 0x206);
+        assert_eq!(exec(&[0x60, 0x12, 0x61, 0x12, 0x90, 0x10], 3).pc.0, 0x206);
+        assert_eq!(exec(&[0x60, 0x12, 0x61, 0x13, 0x90, 0x10], 3).pc.0, 0x208);
+    }
+
+    #[test]
+    fn load_and_add_imm() {
+        let chip = exec(&[0x6A, 0xFE, 0x7A, 0x03], 2);
+        assert_eq!(chip.regs[0xA].0, 0x01);
+        assert_eq!(chip.regs[0xF].0, 0, "7XNN never touches VF");
+    }
+
+    /// Execute `8XYn` with VX = `x`, VY = `y`, returning (VX, VF).
+    fn alu(n: u8, x: u8, y: u8) -> (u8, u8) {
+        let chip = exec(&[0x6F, 0xAA, 0x60, x, 0x61, y, 0x80, 0x10 | n], 4);
+        (chip.regs[0].0, chip.regs[0xF].0)
+    }
+
+    #[test]
+    fn logic() {
+        assert_eq!(alu(0x0, 0x0C, 0x0A).0, 0x0A);
+        assert_eq!(alu(0x1, 0x0C, 0x0A).0, 0x0E);
+        assert_eq!(alu(0x2, 0x0C, 0x0A).0, 0x08);
+        assert_eq!(alu(0x3, 0x0C, 0x0A).0, 0x06);
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(alu(0x4, 0x10, 0x20), (0x30, 0));
+        assert_eq!(alu(0x4, 0xF0, 0x20), (0x10, 1));
+    }
+
+    #[test]
+    fn sub() {
+        assert_eq!(alu(0x5, 0x30, 0x10), (0x20, 1));
+        assert_eq!(alu(0x5, 0x10, 0x30), (0xE0, 0));
+        assert_eq!(alu(0x7, 0x10, 0x30), (0x20, 1));
+        assert_eq!(alu(0x7, 0x30, 0x10), (0xE0, 0));
+    }
+
+    #[test]
+    fn shift() {
+        assert_eq!(alu(0x6, 0x00, 0x05), (0x02, 1));
+        assert_eq!(alu(0x6, 0x00, 0x04), (0x02, 0));
+        assert_eq!(alu(0xE, 0x00, 0x81), (0x02, 1));
+        assert_eq!(alu(0xE, 0x00, 0x41), (0x82, 0));
+    }
+
+    #[test]
+    fn flag_wins_over_result() {
+        let chip = exec(&[0x6F, 0xF0, 0x61, 0x20, 0x8F, 0x14], 3);
+        assert_eq!(chip.regs[0xF].0, 1);
+    }
+
+    #[test]
+    fn load_i() {
+        assert_eq!(exec(&[0xA1, 0x23], 1).reg_i.0, 0x123);
+        assert_eq!(exec(&[0xA1, 0x23, 0x60, 0x05, 0xF0, 0x1E], 3).reg_i.0, 0x128);
+    }
+
+    #[test]
+    fn random() {
+        assert_eq!(exec(&[0x60, 0xFF, 0xC0, 0x00], 2).regs[0].0, 0);
+        for _ in 0..16 {
+            assert_eq!(exec(&[0xC0, 0x0F], 1).regs[0].0 & 0xF0, 0);
+        }
+    }
+
+    #[test]
+    fn random_seed() {
+        let program = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0xC3, 0xFF];
+        let seeded = |seed| {
+            let mut chip = Chip::default();
+            chip.set_rng(Box::new(XorShift::new(seed)));
+            run(&mut chip, &mut Default::default(), &program, 4);
+            chip.regs()[..4].to_vec()
+        };
+        assert_eq!(seeded(7), seeded(7));
+        assert_ne!(seeded(7), seeded(8));
+        assert_eq!(seeded(0), exec(&program, 4).regs()[..4].to_vec());
+    }
+
+    #[test]
+    fn draw() {
+        let program = [0x60, 0x05, 0x61, 0x06, 0xA2, 0x0A, 0xD0, 0x12, 0x00, 0x00, 0xAB, 0xCD];
+        let mut p = Mock::default();
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &program, 4);
+        assert_eq!((row(&chip, 1, 5, 6), row(&chip, 1, 5, 7)), (0xAB, 0xCD));
+        assert_eq!(lit(&chip), 10);
+        assert_eq!(chip.regs[0xF].0, 0);
+
+        chip.pc.0 = 0x206;
+        run(&mut chip, &mut p, &program, 1);
+        assert_eq!(chip.regs[0xF].0, 1, "collision");
+        assert_eq!(lit(&chip), 0);
+        run(&mut chip, &mut p, &program, 1);
+        assert_eq!(chip.regs[0xF].0, 1);
+    }
+
+    #[test]
+    fn skip_key() {
+        let mut p = Mock::default();
+        p.keydowns[7] = true;
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &[0x60, 0x07, 0xE0, 0x9E], 2);
+        assert_eq!(chip.pc.0, 0x206);
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &[0x60, 0x07, 0xE0, 0xA1], 2);
+        assert_eq!(chip.pc.0, 0x204);
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &[0x60, 0x06, 0xE0, 0xA1], 2);
+        assert_eq!(chip.pc.0, 0x206);
+        // Out of the keypad: key F.
+        p.keydowns[0xF] = true;
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &[0x60, 0xFF, 0xE0, 0x9E], 2);
+        assert_eq!(chip.pc.0, 0x206);
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &[0x60, 0x17, 0xE0, 0xA1], 2);
+        assert_eq!(chip.pc.0, 0x204);
+    }
+
+    #[test]
+    fn wait_key() {
+        let mut p = Mock { script: vec![1 << 0xB, 1 << 0xB, 0], ..Default::default() };
+        let mut chip = Chip::default();
+        chip.load(0x200, &[0xF3, 0x0A, 0x00, 0xE0]);
+        chip.reg_delay = 10;
+        for _ in 0..3 {
+            assert_eq!(chip.frame(100, &mut p).unwrap(), Status::Running);
+            assert_eq!(chip.pc.0, 0x200, "waits across frames");
+            assert!(chip.waiting_for_key());
+        }
+        assert_eq!(chip.reg_delay, 7, "timers keep ticking");
+        assert_eq!(chip.cycle(&mut p).unwrap(), Flow::Next, "pressed, then released");
+        assert_eq!((chip.regs[3].0, chip.pc.0), (0xB, 0x202));
+        assert!(!chip.waiting_for_key());
+    }
+
+    #[test]
+    fn timers() {
+        let chip = exec(&[0x60, 0x2A, 0xF0, 0x15, 0xF0, 0x18, 0xF1, 0x07], 4);
+        assert_eq!(chip.reg_delay, 0x2A);
+        assert_eq!(chip.reg_sound, 0x2A);
+        assert_eq!(chip.regs[1].0, 0x2A);
+    }
+
+    #[test]
+    fn font() {
+        let mut chip = exec(&[0x60, 0x0A, 0xF0, 0x29], 2);
+        assert_eq!(chip.reg_i.0, chip.font.glyph_addr(0xA));
+        let i = chip.reg_i.0;
+        assert_eq!(ram(&mut chip, i, 5), font::GLYPHS[0xA].to_vec());
+    }
+
+    #[test]
+    fn custom_font_survives_reset() {
+        let mut chip = Chip::default();
+        chip.set_font(Font { addr: 0x100, glyphs: [[0x11; 5]; 16], ..Default::default() });
+        chip.reset();
+        assert_eq!(chip.font().addr, 0x100);
+        assert_eq!(ram(&mut chip, 0x100 + 5 * 15, 5), vec![0x11; 5]);
+    }
+
+    #[test]
+    fn bcd() {
+        let mut chip = exec(&[0x60, 0xFE, 0xA3, 0x00, 0xF0, 0x33], 3);
+        assert_eq!(ram(&mut chip, 0x300, 3), vec![2, 5, 4]);
+        let mut chip = exec(&[0x60, 0x07, 0xA3, 0x00, 0xF0, 0x33], 3);
+        assert_eq!(ram(&mut chip, 0x300, 3), vec![0, 0, 7]);
+    }
+
+    #[test]
+    fn store_and_load() {
+        let mut chip = exec(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA3, 0x00, 0xF1, 0x55], 5);
+        assert_eq!(ram(&mut chip, 0x300, 3), vec![1, 2, 0]);
+
+        chip.load(0x300, &[9, 8, 7]);
+        chip.pc.0 = 0x200;
+        run(&mut chip, &mut Default::default(), &[0xA3, 0x00, 0xF2, 0x65], 2);
+        assert_eq!(chip.regs[..4].iter().map(|r| r.0).collect::<Vec<_>>(), vec![9, 8, 7, 0]);
+    }
+
+    #[test]
+    fn scroll() {
+        let program = [0x00, 0xC5, 0x00, 0xFB, 0x00, 0xFC];
+        let mut p = Mock::default();
+        let mut chip = Chip::default();
+        chip.screen.draw(1, 8, 0, &[0x81], false);
+        run(&mut chip, &mut p, &program, 1);
+        assert_eq!(row(&chip, 1, 8, 5), 0x81);
+        run(&mut chip, &mut p, &program, 1);
+        assert_eq!(row(&chip, 1, 10, 5), 0x81, "2 lores pixels");
+        run(&mut chip, &mut p, &program, 1);
+        assert_eq!(row(&chip, 1, 8, 5), 0x81);
+        assert_eq!(lit(&chip), 2);
+
+        let mut chip = Chip::default();
+        chip.set_hires(true);
+        chip.screen.draw(1, 8, 0, &[0x81], false);
+        run(&mut chip, &mut p, &[0x00, 0xFB], 1);
+        assert_eq!(row(&chip, 1, 12, 0), 0x81, "4 hires pixels");
+    }
+
+    #[test]
+    fn resolution() {
+        let mut p = Mock::default();
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &[0x00, 0xFF], 1);
+        assert_eq!((chip.screen.width(), chip.screen.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(chip.screen_size(), (128, 64));
+        run(&mut chip, &mut p, &[0x00, 0xFF, 0x00, 0xFE], 1);
+        assert_eq!((chip.screen.width(), chip.screen.height()), (LORES_WIDTH, LORES_HEIGHT));
+    }
+
+    #[test]
+    fn exit() {
+        let mut chip = Chip::default();
+        assert!(run(&mut chip, &mut Default::default(), &[0x00, 0xFD], 1));
+        assert_eq!(chip.pc.0, 0x200, "stays halted");
+        assert_eq!(chip.frame(10, &mut Mock::default()).unwrap(), Status::Exited);
+    }
+
+    #[test]
+    fn draw_16x16() {
+        let mut sprite = vec![0x00, 0xE0, 0x60, 0x30, 0x61, 0x00, 0xA2, 0x0C, 0xD0, 0x10, 0x00, 0x00];
+        sprite.extend((0..32).map(|i| i as u8));
+        let mut p = Mock::default();
+        let mut chip = Chip::default();
+        chip.set_quirks(Quirks::SCHIP);
+        run(&mut chip, &mut p, &sprite, 5);
+        for y in 0..16 {
+            assert_eq!((row(&chip, 1, 0x30, y), row(&chip, 1, 0x38, y)), (2 * y as u8, 2 * y as u8 + 1));
+        }
+
+        // Right half falls off the edge of the hires screen.
+        chip.pc.0 = 0x200;
+        sprite[1] = 0xFF;
+        sprite[3] = 0x78;
+        run(&mut chip, &mut p, &sprite, 5);
+        for y in 0..16 {
+            assert_eq!((row(&chip, 1, 0x78, y), row(&chip, 1, 0, y)), (2 * y as u8, 0));
+        }
+    }
+
+    #[test]
+    fn big_font() {
+        let mut chip = exec(&[0x60, 0x07, 0xF0, 0x30], 2);
+        assert_eq!(chip.reg_i.0, chip.font.big_glyph_addr(7));
+        let i = chip.reg_i.0;
+        assert_eq!(ram(&mut chip, i, 10), font::BIG_GLYPHS[7].to_vec());
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let mut chip = exec(&[0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xF1, 0x75], 4);
+        assert_eq!(chip.rpl_flags()[..3], [0x11, 0x22, 0x00]);
+        chip.reset();
+        run(&mut chip, &mut Default::default(), &[0xF2, 0x85], 1);
+        assert_eq!(chip.regs[..3].iter().map(|r| r.0).collect::<Vec<_>>(), vec![0x11, 0x22, 0x00]);
+    }
+
+    #[test]
+    fn scroll_up() {
+        let mut chip = Chip::default();
+        chip.screen.draw(1, 0, 5, &[0xFF], false);
+        run(&mut chip, &mut Mock::default(), &[0x00, 0xD3], 1);
+        assert_eq!(row(&chip, 1, 0, 2), 0xFF);
+        assert_eq!(lit(&chip), 8);
+    }
+
+    #[test]
+    fn long_i() {
+        let chip = exec(&[0xF0, 0x00, 0xAB, 0xCD], 1);
+        assert_eq!(chip.reg_i.0, 0xABCD);
+        assert_eq!(chip.pc.0, 0x204);
+    }
+
+    #[test]
+    fn skip_long_i() {
+        let chip = exec(&[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x00, 0xE0], 1);
+        assert_eq!(chip.pc.0, 0x206, "skips over both words");
+        let chip = exec(&[0x40, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x00, 0xE0], 1);
+        assert_eq!(chip.pc.0, 0x202);
+    }
+
+    #[test]
+    fn save_and_load_range() {
+        let program = [0x62, 0x02, 0x63, 0x03, 0x64, 0x04, 0xA3, 0x00, 0x52, 0x42, 0x54, 0x22];
+        let mut chip = exec(&program, 5);
+        assert_eq!(ram(&mut chip, 0x300, 3), vec![2, 3, 4]);
+        assert_eq!(chip.reg_i.0, 0x300, "I is untouched");
+        run(&mut chip, &mut Default::default(), &program, 1);
+        assert_eq!(ram(&mut chip, 0x300, 3), vec![4, 3, 2], "descending when X > Y");
+
+        chip.pc.0 = 0x200;
+        run(&mut chip, &mut Default::default(), &[0x57, 0x93], 1);
+        assert_eq!(chip.regs[7..10].iter().map(|r| r.0).collect::<Vec<_>>(), vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn planes() {
+        let program = [0xF3, 0x01, 0xA2, 0x08, 0xD0, 0x02, 0x00, 0x00, 0x11, 0x22, 0x33, 0x44];
+        let mut p = Mock::default();
+        let mut chip = Chip::default();
+        chip.set_quirks(Quirks::XOCHIP);
+        run(&mut chip, &mut p, &program, 3);
+        assert_eq!((row(&chip, 1, 0, 0), row(&chip, 1, 0, 1)), (0x11, 0x22));
+        assert_eq!((row(&chip, 2, 0, 0), row(&chip, 2, 0, 1)), (0x33, 0x44));
+        assert_eq!(chip.planes, 3);
+
+        chip.pc.0 = 0x200;
+        run(&mut chip, &mut p, &[0xF2, 0x01, 0xA2, 0x08, 0xD0, 0x02], 3);
+        assert_eq!((row(&chip, 1, 0, 0), row(&chip, 1, 0, 1)), (0x11, 0x22), "only the second plane drawn");
+        assert_eq!((row(&chip, 2, 0, 0), row(&chip, 2, 0, 1)), (0x33 ^ 0x11, 0x44 ^ 0x22));
+    }
+
+    #[test]
+    fn audio() {
+        let mut program = vec![0xA2, 0x08, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A];
+        program.extend(0..16);
+        let mut p = Mock::default();
+        let mut chip = Chip::default();
+        run(&mut chip, &mut p, &program, 2);
+        let pattern = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        assert_eq!(p.pattern, Some((pattern, 64)));
+        run(&mut chip, &mut p, &program, 2);
+        assert_eq!(p.pattern, Some((pattern, 0x70)));
+    }
+
+    fn exec_with(quirks: Quirks, program: &[u8], n: usize) -> Chip {
+        let mut chip = Chip::default();
+        chip.set_quirks(quirks);
+        run(&mut chip, &mut Default::default(), program, n);
+        chip
+    }
+
+    #[test]
+    fn quirk_shift() {
+        let program = [0x60, 0x03, 0x61, 0x40, 0x80, 0x16];
+        let vy = Quirks { shift_vy: true, ..Quirks::VIP };
+        let vx = Quirks { shift_vy: false, ..Quirks::VIP };
+        assert_eq!(exec_with(vy, &program, 3).regs[0].0, 0x20);
+        assert_eq!(exec_with(vx, &program, 3).regs[0].0, 0x01);
+        assert_eq!(exec_with(vx, &program, 3).regs[0xF].0, 1);
+    }
+
+    #[test]
+    fn quirk_load_store() {
+        use quirks::LoadStore;
+        let program = [0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65];
+        let i = |load_store| exec_with(Quirks { load_store, ..Quirks::VIP }, &program, 3).reg_i.0;
+        assert_eq!(i(LoadStore::Keep), 0x300);
+        assert_eq!(i(LoadStore::AddX), 0x304);
+        assert_eq!(i(LoadStore::AddXPlusOne), 0x306);
+    }
+
+    #[test]
+    fn quirk_jump() {
+        let program = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x00];
+        assert_eq!(exec_with(Quirks::VIP, &program, 3).pc.0, 0x310);
+        assert_eq!(exec_with(Quirks::CHIP48, &program, 3).pc.0, 0x320);
+    }
+
+    #[test]
+    fn quirk_vf_reset() {
+        for &n in &[0x11, 0x12, 0x13] {
+            let program = [0x6F, 0x05, 0x80, n];
+            assert_eq!(exec_with(Quirks::VIP, &program, 2).regs[0xF].0, 0);
+            assert_eq!(exec_with(Quirks::SCHIP, &program, 2).regs[0xF].0, 5);
+        }
+    }
+
+    #[test]
+    fn quirk_clip() {
+        let program = [0x60, 0x7C, 0x61, 0x3E, 0xA2, 0x0A, 0xD0, 0x13, 0x00, 0x00, 0xFF, 0xFF, 0xFF];
+        let draw = |clip| {
+            let mut chip = Chip::default();
+            chip.set_quirks(Quirks { clip, ..Quirks::VIP });
+            run(&mut chip, &mut Mock::default(), &program, 4);
+            chip
+        };
+        // Position wraps from (124, 62) to (60, 30) in both cases.
+        let chip = draw(true);
+        assert_eq!((row(&chip, 1, 56, 30), row(&chip, 1, 56, 31)), (0x0F, 0x0F));
+        assert_eq!(lit(&chip), 8);
+        let chip = draw(false);
+        assert_eq!((row(&chip, 1, 56, 30), row(&chip, 1, 0, 30)), (0x0F, 0xF0));
+        assert_eq!((row(&chip, 1, 56, 0), row(&chip, 1, 0, 0)), (0x0F, 0xF0));
+        assert_eq!(lit(&chip), 24);
+    }
+
+    #[test]
+    fn quirk_display_wait() {
+        let program = [0xD0, 0x01, 0xD0, 0x01];
+        let mut chip = Chip::default();
+        chip.set_quirks(Quirks { display_wait: true, ..Quirks::VIP });
+        chip.load(0x200, &program);
+        assert_eq!(chip.cycle(&mut Mock::default()).unwrap(), Flow::Wait);
+
+        let mut chip = Chip::default();
+        chip.set_quirks(Quirks { display_wait: false, ..Quirks::VIP });
+        chip.load(0x200, &program);
+        assert_eq!(chip.cycle(&mut Mock::default()).unwrap(), Flow::Next);
+    }
+
+    #[test]
+    fn quirks_survive_reset() {
+        let mut chip = Chip::default();
+        chip.set_quirks(Quirks::SCHIP);
+        chip.reset();
+        assert_eq!(*chip.quirks(), Quirks::SCHIP);
+        assert_eq!(Quirks::preset("chip48"), Some(Quirks::CHIP48));
+    }
+
+    #[test]
+    fn watch_write() {
+        use watch::{Access, Action};
+        let mut chip = Chip::default();
+        let mut w = Watchpoint::new(0x301, 0x302);
+        w.read = false;
+        w.action = Action::Log;
+        chip.add_watchpoint(w);
+        // v0 := 1, v1 := 2, i := 0x300, save v1, load v1
+        run(&mut chip, &mut Default::default(), &[0x60, 0x01, 0x61, 0x02, 0xA3, 0x00, 0xF1, 0x55, 0xA3, 0x00, 0xF1, 0x65], 6);
+        assert_eq!(chip.take_hits(), vec![
+            Hit { pc: 0x206, addr: 0x301, access: Access::Write, value: 2, action: Action::Log },
+        ]);
+        assert!(chip.take_hits().is_empty());
+
+        chip.reset();
+        chip.remove_watchpoint(&w);
+        assert!(chip.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn watch_read_execute() {
+        use watch::Access;
+        let mut chip = Chip::default();
+        // i := 0x206, load v0, self: jump self
+        chip.load(0x200, &[0xA2, 0x06, 0xF0, 0x65, 0x12, 0x04, 0xAB]);
+        chip.add_watchpoint(Watchpoint::new(0x204, 0x206));
+        run(&mut chip, &mut Default::default(), &[], 3);
+        let hits: Vec<_> = chip.take_hits().iter().map(|h| (h.pc, h.addr, h.access, h.value)).collect();
+        assert_eq!(hits, vec![
+            (0x202, 0x206, Access::Read, 0xAB),
+            (0x204, 0x204, Access::Execute, 0x12),
+            (0x204, 0x205, Access::Execute, 0x04),
+        ]);
+    }
+
+    fn fault(program: &[u8], n: usize) -> (Chip, Error) {
+        let mut chip = Chip::default();
+        let e = try_run(&mut chip, &mut Default::default(), program, n).unwrap_err();
+        (chip, e)
+    }
+
+    #[test]
+    fn unknown_instruction() {
+        let (chip, e) = fault(&[0x60, 0x00, 0x50, 0x01], 2);
+        assert_eq!(e, Error::UnknownInstruction { addr: 0x202, inst: 0x5001 });
+        assert_eq!(chip.pc.0, 0x202, "pc stays at the faulting instruction");
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let (_, e) = fault(&[0x00, 0xEE], 1);
+        assert_eq!(e, Error::StackUnderflow { addr: 0x200 });
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let (chip, e) = fault(&[0x22, 0x00], STACK_DEPTH + 1);
+        assert_eq!(e, Error::StackOverflow { addr: 0x200 });
+        assert_eq!(chip.stack.len(), STACK_DEPTH);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut chip = Chip::default();
+        chip.reg_i.0 = 0xFFFE;
+        let e = try_run(&mut chip, &mut Mock::default(), &[0xF2, 0x55], 1).unwrap_err();
+        assert_eq!(e, Error::OutOfRange { addr: 0x200, start: 0xFFFE, len: 3 });
+        let mut chip = Chip::default();
+        chip.pc.0 = 0xFFFF;
+        let e = chip.cycle(&mut Mock::default()).unwrap_err();
+        assert_eq!(e, Error::OutOfRange { addr: 0xFFFF, start: 0xFFFF, len: 2 });
+    }
+}
+
+/// Levels of nested subroutine calls.
+pub const STACK_DEPTH: usize = 16;
+/// Size of the screen in pixels, in low resolution.
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+/// Size of the screen in pixels, in SUPER-CHIP high resolution.
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+/// Number of RPL user flags for `FX75`/`FX85`.
+pub const RPL_FLAGS: usize = 16;
+/// Number of XO-CHIP bitplanes.
+pub const PLANES: usize = 2;
+/// First word of the only 4-byte instruction, `F000 NNNN`.
+const LONG_I: u16 = 0xF000;
 
 pub struct Chip {
     /// Program begins at 0x0200
     ram: Ram,
+    /// At most `STACK_DEPTH` return addresses
     stack: Vec<u16>,
     pc: Wrapping<u16>,
     /// R0 ~ RF, sixteen 8-bit registers
@@ -26,24 +682,207 @@ pub struct Chip {
     reg_delay: u8,
     /// Count down one frame at a time. When not zero, buzz the speaker
     reg_sound: u8,
+    /// Glyphs pointed to by `FX29`, kept in RAM across `reset`.
+    font: Font,
+    /// Platform behaviors, kept across `reset`.
+    quirks: Quirks,
+    /// 128x64 instead of 64x32 screen
+    hires: bool,
+    /// Presented to the peripheral every frame
+    screen: Framebuffer,
+    /// HP-48 RPL user flags, kept across `reset` like the calculator does.
+    rpl: [u8; RPL_FLAGS],
+    /// Bitmask of the XO-CHIP bitplanes being drawn to
+    planes: u8,
+    /// While `FX0A` waits, the keys seen down so far, bit N for key N
+    key_wait: Option<u16>,
+    /// XO-CHIP audio, 128 1-bit samples
+    pattern: [u8; 16],
+    /// XO-CHIP audio playback rate
+    pitch: u8,
+    /// For `CXNN`, kept across `reset`.
+    rng: Box<dyn Rng>,
+}
+
+/// What happens after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    /// Stop executing until the next frame, after `DXYN` with the display
+    /// wait quirk, or while `FX0A` waits for a key.
+    Wait,
+    /// The program exited.
+    Quit,
+}
+
+/// How a frame ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    /// The program exited with `00FD`.
+    Exited,
+    /// The peripheral asked to quit, e.g. for the user.
+    Quit,
 }
 
 impl Chip {
+    /// Restore the power-on state, keeping the current font, quirks,
+    /// RPL flags, watchpoints and random number generator.
     pub fn reset(&mut self) {
-        *self = Default::default();
+        let font = self.font;
+        let quirks = self.quirks;
+        let rpl = self.rpl;
+        let watchpoints = self.ram.watchpoints().clone();
+        let mut chip = Chip::default();
+        mem::swap(&mut chip.rng, &mut self.rng);
+        *self = chip;
+        self.set_font(font);
+        self.quirks = quirks;
+        self.rpl = rpl;
+        *self.ram.watchpoints() = watchpoints;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
     }
 
+    /// Place `font` into RAM and use it for `FX29`/`FX30` from now on.
+    pub fn set_font(&mut self, font: Font) {
+        self.load(font.addr, &font.bytes());
+        self.load(font.big_addr, &font.big_bytes());
+        self.font = font;
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    /// What has been drawn, as presented at the end of each frame.
+    pub fn screen(&self) -> &Framebuffer {
+        &self.screen
+    }
+
+    /// Current size of the screen in pixels.
+    pub fn screen_size(&self) -> (usize, usize) {
+        if self.hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (LORES_WIDTH, LORES_HEIGHT) }
+    }
+
+    /// Draw the random numbers of `CXNN` from `rng`, by default an
+    /// `XorShift` seeded with 0.
+    pub fn set_rng(&mut self, rng: Box<dyn Rng>) {
+        self.rng = rng;
+    }
+
+    /// RPL flags, for the host to persist them.
+    pub fn rpl_flags(&self) -> &[u8; RPL_FLAGS] {
+        &self.rpl
+    }
+
+    pub fn set_rpl_flags(&mut self, rpl: [u8; RPL_FLAGS]) {
+        self.rpl = rpl;
+    }
+
+    /// Copy `data` into RAM at `addr`.
+    ///
+    /// # Panics
+    /// If `data` runs past the end of RAM.
     pub fn load(&mut self, addr: u16, data: &[u8]) {
-        let mut i = addr;
-        for &x in data {
-            self.ram.write(i as usize, x);
-            i += 1;
+        for (i, &x) in (addr as usize..).zip(data) {
+            self.ram.write(i, x).expect("data does not fit into RAM");
+        }
+    }
+
+    /// Address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.pc.0
+    }
+
+    /// Whether `FX0A` at `pc` is waiting for a key to be pressed and
+    /// released.
+    pub fn waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    /// Continue execution at `addr`, e.g. where a program was loaded.
+    pub fn set_pc(&mut self, addr: u16) {
+        self.pc = Wrapping(addr);
+    }
+
+    /// V0 ~ VF.
+    pub fn regs(&self) -> [u8; 16] {
+        let mut regs = [0; 16];
+        for (r, v) in regs.iter_mut().zip(&self.regs) {
+            *r = v.0;
         }
+        regs
+    }
+
+    pub fn reg_i(&self) -> u16 {
+        self.reg_i.0
+    }
+
+    /// The delay and sound timers.
+    pub fn timers(&self) -> (u8, u8) {
+        (self.reg_delay, self.reg_sound)
+    }
+
+    /// Return addresses, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// Up to `len` bytes of RAM from `addr`, fewer at the end of RAM.
+    pub fn memory(&self, addr: u16, len: usize) -> &[u8] {
+        self.ram.peek(addr as usize, len)
     }
 
-    pub fn frame<P>(&mut self, num_cycle: usize, peripheral: &mut P) -> bool
-        where P: Timer + Video + Audio + Input
+    /// Report accesses to RAM matching `w` as hits.
+    pub fn add_watchpoint(&mut self, w: Watchpoint) {
+        self.ram.watchpoints().push(w);
+    }
+
+    pub fn remove_watchpoint(&mut self, w: &Watchpoint) {
+        self.ram.watchpoints().retain(|x| x != w);
+    }
+
+    pub fn watchpoints(&mut self) -> &[Watchpoint] {
+        self.ram.watchpoints()
+    }
+
+    /// Accesses under watchpoints since last taken, oldest first.
+    /// Up to `watch::MAX_HITS` are kept.
+    pub fn take_hits(&mut self) -> Vec<Hit> {
+        self.ram.take_hits()
+    }
+
+    /// Run one frame: tick the timers, execute `num_cycle` instructions,
+    /// then present the screen and wait for the next frame.
+    /// Returns how the frame ended, or the fault of the program.
+    /// A faulting instruction is not executed; `pc` is left pointing at it.
+    pub fn frame<P>(&mut self, num_cycle: usize, peripheral: &mut P) -> Result<Status>
+        where P: Timer + Video + Audio + Input + Machine
     {
+        self.tick(peripheral);
+
+        for _ in 0..num_cycle {
+            match self.cycle(peripheral)? {
+                Flow::Next => (),
+                Flow::Wait => break,
+                Flow::Quit => return Ok(Status::Exited),
+            }
+        }
+
+        peripheral.present(&self.screen);
+        Ok(if peripheral.pump() { Status::Quit } else { Status::Running })
+    }
+
+    /// Count the timers down, buzzing while the sound timer runs.
+    /// Done at the start of every frame.
+    pub fn tick<P: Audio>(&mut self, peripheral: &mut P) {
         if self.reg_delay > 0 { self.reg_delay -= 1 }
         if self.reg_sound > 0 {
             self.reg_sound -= 1;
@@ -51,15 +890,19 @@ impl Chip {
         } else {
             peripheral.buzz(false);
         }
+    }
 
-        for _ in 0..num_cycle {
-            if self.cycle(peripheral) {
-                return true;
-            }
+    /// Execute the instruction at `pc`.
+    /// A faulting instruction is not executed; `pc` is left pointing at it.
+    pub fn cycle<Peripheral>(&mut self, p: &mut Peripheral) -> Result<Flow>
+        where Peripheral: Audio + Input + Machine
+    {
+        let addr = self.pc.0;
+        let result = self.execute(p, addr);
+        if result.is_err() {
+            self.pc.0 = addr;
         }
-
-        peripheral.present();
-        peripheral.pump()
+        result
     }
 
 
@@ -73,107 +916,245 @@ impl Chip {
             reg_i: Wrapping(0),
             reg_delay: 0,
             reg_sound: 0,
+            font: Default::default(),
+            quirks: Default::default(),
+            hires: false,
+            screen: Default::default(),
+            rpl: [0; RPL_FLAGS],
+            planes: 1,
+            key_wait: None,
+            pattern: [0; 16],
+            pitch: 64,
+            rng: Box::new(XorShift::default()),
         };
-        // TODO: load font data
+        let font = chip.font;
+        chip.set_font(font);
         chip
     }
 
-    fn cycle<Peripheral>(&mut self, p: &mut Peripheral) -> bool
-        where Peripheral: Video + Audio + Input
+    fn execute<Peripheral>(&mut self, p: &mut Peripheral, addr: u16) -> Result<Flow>
+        where Peripheral: Audio + Input + Machine
     {
+        use instruction::Instruction::*;
+
         const INST_SIZE: Wrapping<u16> = Wrapping(2);
         const MSB: Wrapping<u8> = Wrapping(0b1000_0000);
         const LSB: Wrapping<u8> = Wrapping(0b0000_0001);
 
-        let inst: u16 = self.ram.read(self.pc.0 as usize);
-        self.pc = self.pc + INST_SIZE;
+        let out_of_range = |e: ram::OutOfRange| Error::OutOfRange { addr, start: e.start, len: e.len };
 
-        let mut stop = false;
+        let inst = self.ram.fetch(addr as usize, addr).map_err(out_of_range)?;
+        let inst = instruction::decode(inst).ok_or(Error::UnknownInstruction { addr, inst })?;
+        self.pc += INST_SIZE;
 
-        //println!("decode {:04X}", inst);    // TODO: remove this
-        (|| {
-            decode! { inst =>
-                "00E0" => () { p.clear() }
-                "00EE" => () { self.pc.0 = self.stack.pop().unwrap() }
-                "1NNN" => (n) { self.pc.0 = n }
-                "2NNN" => (n) { self.stack.push(self.pc.0); self.pc.0 = n }
-                "3XNN" => (x, n) { if self.regs[x].0 == n as u8 { self.pc = self.pc + INST_SIZE } }
-                "4XNN" => (x, n) { if self.regs[x].0 != n as u8 { self.pc = self.pc + INST_SIZE } }
-                "5XY0" => (x, y) { if self.regs[x] == self.regs[y] { self.pc = self.pc + INST_SIZE } }
-                "6XNN" => (x, n) { self.regs[x].0 = n as u8 }
-                "7XNN" => (x, n) { self.regs[x] = self.regs[x] + Wrapping(n as u8) }
-                "8XY0" => (x, y) { self.regs[x] = self.regs[y] }
-                "8XY1" => (x, y) { self.regs[x] = self.regs[x] | self.regs[y] }
-                "8XY2" => (x, y) { self.regs[x] = self.regs[x] & self.regs[y] }
-                "8XY3" => (x, y) { self.regs[x] = self.regs[x] ^ self.regs[y] }
-                "8XY4" => (x, y) {
-                    let rx = self.regs[x].0 as u16;
-                    let ry = self.regs[y].0 as u16;
-                    let r = rx + ry;
-                    self.regs[0xF].0 = (r >> 8) as u8;  // >>8 to fetch the carry flag
-                    self.regs[x].0 = r as u8;
-                }
-                "8XY5" => (x, y) {
-                    let rx = self.regs[x].0 as i16;
-                    let ry = self.regs[y].0 as i16;
-                    let r = rx - ry;
-                    self.regs[0xF].0 = if r < 0 { 0 } else { 1 };
-                    self.regs[x].0 = r as u8;
-                }
-                "8XY6" => (x, y) {
-                    self.regs[0xF] = self.regs[y] & LSB;
-                    self.regs[x] = self.regs[y] >> 1;
-                }
-                "8XY7" => (x, y) {
-                    let rx = self.regs[x].0 as i16;
-                    let ry = self.regs[y].0 as i16;
-                    let r = ry - rx;
-                    self.regs[0xF].0 = if r < 0 { 0 } else { 1 };
-                    self.regs[x].0 = r as u8;
-                }
-                "8XYE" => (x, y) {
-                    self.regs[0xF] = (self.regs[y] & MSB) >> 7;
-                    self.regs[x] = self.regs[y] << 1;
-                }
-                "9XY0" => (x, y) { if self.regs[x] != self.regs[y] { self.pc = self.pc + INST_SIZE } }
-                "ANNN" => (n) { self.reg_i.0 = n }
-                "BNNN" => (n) { self.pc.0 = self.regs[0].0 as u16 + n }
-                "CXNN" => (x, n) { self.regs[x].0 = random::<u8>() & n as u8 }
-                "DXYN" => (x, y, n) {
-                    let x = self.regs[x].0 as isize;
-                    let y = self.regs[y].0 as isize;
-                    let sprite = self.ram.slice(self.reg_i.0 as usize, n as usize);
-                    self.regs[0xF].0 = p.draw(x, y, sprite) as u8
-                }
-                "EX9E" => (x) { if  p.keydown(self.regs[x].0 as usize) { self.pc = self.pc + INST_SIZE } }
-                "EXA1" => (x) { if !p.keydown(self.regs[x].0 as usize) { self.pc = self.pc + INST_SIZE } }
-                "FX07" => (x) { self.regs[x].0 = self.reg_delay }
-                "FX0A" => (x) {
-                    if let Some(k) = p.key() {
-                        self.regs[x].0 = k;
-                    } else {
-                        stop = true;
-                    }
-                }
-                "FX15" => (x) { self.reg_delay = self.regs[x].0 }
-                "FX18" => (x) { self.reg_sound = self.regs[x].0 }
-                "FX1E" => (x) { self.reg_i = self.reg_i + Wrapping(self.regs[x].0 as u16) }
-                "FX55" => (x) {
-                    let slice: &mut [u8] = self.ram.slice(self.reg_i.0 as usize, x+1);
-                    let pairs = slice.iter_mut().zip(self.regs.iter().map(|w| w.0));
-                    for (m, x) in pairs { *m = x }
+        let quirks = self.quirks;
+        let mut flow = Flow::Next;
+
+        match inst {
+            ScrollDown(n) => self.screen.scroll(self.planes, 0, n as isize),
+            ScrollUp(n) => self.screen.scroll(self.planes, 0, -(n as isize)),
+            Clear => self.screen.clear(self.planes),
+            Return => self.pc.0 = self.stack.pop().ok_or(Error::StackUnderflow { addr })?,
+            // 4 hires pixels, half as many lores ones (SUPER-CHIP 1.1).
+            ScrollRight => { let n = self.scroll_step(); self.screen.scroll(self.planes, n, 0) },
+            ScrollLeft => { let n = self.scroll_step(); self.screen.scroll(self.planes, -n, 0) },
+            Exit => { self.pc.0 = addr; flow = Flow::Quit }
+            LoRes => self.set_hires(false),
+            HiRes => self.set_hires(true),
+            Sys(nnn) => p.sys(nnn),
+            Jump(nnn) => self.pc.0 = nnn,
+            Call(nnn) => {
+                if self.stack.len() == STACK_DEPTH { return Err(Error::StackOverflow { addr }) }
+                self.stack.push(self.pc.0);
+                self.pc.0 = nnn;
+            }
+            SkipEqImm { x, nn } => if self.regs[x as usize].0 == nn { self.skip() },
+            SkipNeImm { x, nn } => if self.regs[x as usize].0 != nn { self.skip() },
+            SkipEq { x, y } => if self.regs[x as usize] == self.regs[y as usize] { self.skip() },
+            SaveRange { x, y } => {
+                let values: Vec<u8> = self.reg_range(x as usize, y as usize).into_iter().map(|r| self.regs[r].0).collect();
+                self.ram.write(self.reg_i.0 as usize, &values[..]).map_err(out_of_range)?;
+            }
+            LoadRange { x, y } => {
+                let regs = self.reg_range(x as usize, y as usize);
+                let slice: &[u8] = self.ram.slice(self.reg_i.0 as usize, regs.len()).map_err(out_of_range)?;
+                for (&m, &r) in slice.iter().zip(&regs) { self.regs[r].0 = m }
+            }
+            LoadImm { x, nn } => self.regs[x as usize].0 = nn,
+            AddImm { x, nn } => self.regs[x as usize] += Wrapping(nn),
+            Move { x, y } => self.regs[x as usize] = self.regs[y as usize],
+            Or { x, y } => { self.regs[x as usize] |= self.regs[y as usize]; self.reset_vf() }
+            And { x, y } => { self.regs[x as usize] &= self.regs[y as usize]; self.reset_vf() }
+            Xor { x, y } => { self.regs[x as usize] ^= self.regs[y as usize]; self.reset_vf() }
+            // VF is written last, so the flag wins when X is F.
+            Add { x, y } => {
+                let rx = self.regs[x as usize].0 as u16;
+                let ry = self.regs[y as usize].0 as u16;
+                let r = rx + ry;
+                self.regs[x as usize].0 = r as u8;
+                self.regs[0xF].0 = (r >> 8) as u8;  // >>8 to fetch the carry flag
+            }
+            Sub { x, y } => {
+                let rx = self.regs[x as usize].0 as i16;
+                let ry = self.regs[y as usize].0 as i16;
+                let r = rx - ry;
+                self.regs[x as usize].0 = r as u8;
+                self.regs[0xF].0 = if r < 0 { 0 } else { 1 };
+            }
+            ShiftRight { x, y } => {
+                let r = self.regs[if quirks.shift_vy { y } else { x } as usize];
+                self.regs[x as usize] = r >> 1;
+                self.regs[0xF] = r & LSB;
+            }
+            SubN { x, y } => {
+                let rx = self.regs[x as usize].0 as i16;
+                let ry = self.regs[y as usize].0 as i16;
+                let r = ry - rx;
+                self.regs[x as usize].0 = r as u8;
+                self.regs[0xF].0 = if r < 0 { 0 } else { 1 };
+            }
+            ShiftLeft { x, y } => {
+                let r = self.regs[if quirks.shift_vy { y } else { x } as usize];
+                self.regs[x as usize] = r << 1;
+                self.regs[0xF] = (r & MSB) >> 7;
+            }
+            SkipNe { x, y } => if self.regs[x as usize] != self.regs[y as usize] { self.skip() },
+            LoadI(nnn) => self.reg_i.0 = nnn,
+            JumpOffset(nnn) => {
+                let x = if quirks.jump_vx { (nnn >> 8) as usize } else { 0 };
+                self.pc.0 = self.regs[x].0 as u16 + nnn;
+            }
+            Random { x, nn } => self.regs[x as usize].0 = self.rng.next_u8() & nn,
+            Draw { x, y, n } => {
+                let (w, h) = self.screen_size();
+                let x = self.regs[x as usize].0 as usize % w;
+                let y = self.regs[y as usize].0 as usize % h;
+                self.regs[0xF].0 = self.draw(x, y, n as usize).map_err(out_of_range)? as u8;
+                if quirks.display_wait { flow = Flow::Wait }
+            }
+            // Only the low nibble of VX names a key.
+            SkipKey { x } => if  p.keydown(self.regs[x as usize].0 as usize & 0xF) { self.skip() },
+            SkipNotKey { x } => if !p.keydown(self.regs[x as usize].0 as usize & 0xF) { self.skip() },
+            LoadLongI => {
+                self.reg_i.0 = self.ram.fetch(self.pc.0 as usize, addr).map_err(out_of_range)?;
+                self.pc += INST_SIZE;
+            }
+            Planes(n) => self.planes = n,
+            Audio => {
+                let pattern = self.ram.slice(self.reg_i.0 as usize, 16).map_err(out_of_range)?;
+                self.pattern.copy_from_slice(pattern);
+                p.pattern(&self.pattern, self.pitch);
+            }
+            GetDelay { x } => self.regs[x as usize].0 = self.reg_delay,
+            WaitKey { x } => {
+                // A key must be pressed and released, as on the COSMAC VIP.
+                // Until then, run again in the next frame.
+                let down = (0..16).filter(|&k| p.keydown(k)).fold(0, |bits, k| bits | 1 << k);
+                let pressed = self.key_wait.unwrap_or(0);
+                let released = pressed & !down;
+                if released != 0 {
+                    self.regs[x as usize].0 = released.trailing_zeros() as u8;
+                    self.key_wait = None;
+                } else {
+                    self.key_wait = Some(pressed | down);
+                    self.pc.0 = addr;
+                    flow = Flow::Wait;
                 }
-                "FX65" => (x) {
-                    let slice: &mut [u8] = self.ram.slice(self.reg_i.0 as usize, x+1);
+            }
+            SetDelay { x } => self.reg_delay = self.regs[x as usize].0,
+            SetSound { x } => self.reg_sound = self.regs[x as usize].0,
+            AddI { x } => self.reg_i += Wrapping(self.regs[x as usize].0 as u16),
+            Font { x } => self.reg_i.0 = self.font.glyph_addr(self.regs[x as usize].0),
+            BigFont { x } => self.reg_i.0 = self.font.big_glyph_addr(self.regs[x as usize].0),
+            Pitch { x } => { self.pitch = self.regs[x as usize].0; p.pattern(&self.pattern, self.pitch) }
+            Bcd { x } => {
+                let rx = self.regs[x as usize].0;
+                let bcd = [rx / 100, rx / 10 % 10, rx % 10];
+                self.ram.write(self.reg_i.0 as usize, &bcd[..]).map_err(out_of_range)?;
+            }
+            Store { x } => {
+                let x = x as usize;
+                let values: Vec<u8> = self.regs[..x+1].iter().map(|w| w.0).collect();
+                self.ram.write(self.reg_i.0 as usize, &values[..]).map_err(out_of_range)?;
+                self.increment_i(x);
+            }
+            Load { x } => {
+                let x = x as usize;
+                {
+                    let slice: &[u8] = self.ram.slice(self.reg_i.0 as usize, x+1).map_err(out_of_range)?;
                     let pairs = slice.iter().zip(self.regs.iter_mut());
                     for (&m, x) in pairs { x.0 = m }
                 }
+                self.increment_i(x);
+            }
+            SaveFlags { x } => {
+                for (f, r) in self.rpl.iter_mut().zip(&self.regs[..x as usize + 1]) { *f = r.0 }
+            }
+            LoadFlags { x } => {
+                for (r, &f) in self.regs[..x as usize + 1].iter_mut().zip(&self.rpl) { r.0 = f }
+            }
+        }
+
+        Ok(flow)
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        let (w, h) = self.screen_size();
+        self.screen.resize(w, h);
+    }
+
+    /// Pixels `00FB`/`00FC` scroll by in the current resolution.
+    fn scroll_step(&self) -> isize {
+        if self.hires { 4 } else { 2 }
+    }
+
+    /// Skip the next instruction, which is 4 bytes long for `F000 NNNN`.
+    fn skip(&mut self) {
+        // Only looked at, not a read by the program.
+        let next = self.ram.peek(self.pc.0 as usize, 2);
+        self.pc += Wrapping(if next == [(LONG_I >> 8) as u8, LONG_I as u8] { 4 } else { 2 });
+    }
+
+    /// VX ~ VY, in descending order if X > Y.
+    fn reg_range(&self, x: usize, y: usize) -> Vec<usize> {
+        if x <= y { (x..y+1).collect() } else { (y..x+1).rev().collect() }
+    }
+
+    /// Draw the sprite at `I` to each selected plane, with the data for
+    /// each plane following the previous. `n` is the number of rows, or
+    /// `0` for a 16x16 sprite of two bytes per row.
+    /// Returns `true` on collision in any plane.
+    fn draw(&mut self, x: usize, y: usize, n: usize) -> result::Result<bool, ram::OutOfRange> {
+        let size = if n == 0 { 32 } else { n };
+        let planes: Vec<u8> = (0..PLANES).map(|i| 1 << i).filter(|&bit| self.planes & bit != 0).collect();
+        let sprites = self.ram.slice(self.reg_i.0 as usize, size * planes.len())?.to_vec();
+
+        let clip = self.quirks.clip;
+        let mut collided = false;
+        for (&plane, sprite) in planes.iter().zip(sprites.chunks(size)) {
+            collided |= if n == 0 {
+                let left: Vec<u8> = sprite.iter().step_by(2).cloned().collect();
+                let right: Vec<u8> = sprite.iter().skip(1).step_by(2).cloned().collect();
+                self.screen.draw(plane, x, y, &left, clip) | self.screen.draw(plane, x + 8, y, &right, clip)
+            } else {
+                self.screen.draw(plane, x, y, sprite, clip)
             };
+        }
+        Ok(collided)
+    }
 
-            panic!("unknown instruction {:04X}", inst);
-        })();
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset { self.regs[0xF].0 = 0 }
+    }
 
-        stop
+    /// After `FX55`/`FX65` accessed V0 ~ VX.
+    fn increment_i(&mut self, x: usize) {
+        self.reg_i += Wrapping(match self.quirks.load_store {
+            quirks::LoadStore::Keep => 0,
+            quirks::LoadStore::AddX => x as u16,
+            quirks::LoadStore::AddXPlusOne => x as u16 + 1,
+        });
     }
 }
 
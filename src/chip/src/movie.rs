@@ -0,0 +1,290 @@
+//! Movies: the input of every frame of a run, to replay it exactly.
+//!
+//! `Recorder` and `Player` wrap the peripheral of `Chip::frame`, telling
+//! frames apart by `Timer::pump`. Along with the same ROM, seed, quirks,
+//! load address and cycles per frame, playing a movie runs the program
+//! exactly as recorded.
+//!
+//! Numbers are big-endian.
+//!
+//! ```text
+//! size    content
+//! 4       "C8MV"
+//! 2       VERSION
+//! 8       ROM hash, see `rom_hash`
+//! 8       seed of `XorShift`
+//! 6       quirks, see `Quirks::to_bytes`
+//! 2       load address
+//! 4       cycles per frame
+//! 4       number of frames N
+//! 2N      keys down in each frame, bit N for key N
+//! ```
+
+use std::error;
+use std::fmt;
+use std::result;
+use bytes::{self, Reader};
+use framebuffer::Framebuffer;
+use peripheral::{Timer, Video, Audio, Input, Machine};
+use quirks::Quirks;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test::Mock;
+    use {Chip, Status, XorShift};
+
+    /// Loop until key 5 is down, then wait for a key to be pressed and
+    /// released, draw a random number and exit.
+    const ROM: &[u8] = &[0x70, 0x01, 0x61, 0x05, 0xE1, 0x9E, 0x12, 0x00, 0xF2, 0x0A, 0xC3, 0xFF, 0x00, 0xFD];
+
+    fn run<P>(p: &mut P, seed: u64) -> Chip
+        where P: Timer + Video + Audio + Input + Machine
+    {
+        let mut chip = Chip::default();
+        chip.set_rng(Box::new(XorShift::new(seed)));
+        chip.load(0x200, ROM);
+        while chip.frame(3, p).unwrap() == Status::Running {}
+        chip
+    }
+
+    #[test]
+    fn record_and_play() {
+        let mut movie = Movie::new(::rom_hash(ROM), 7, Quirks::VIP, 0x200, 3);
+        let mut p = Mock::default();
+        p.keydowns[5] = true;
+        p.script = vec![1 << 5, 0];
+        let recorded = {
+            let mut rec = Recorder::new(&mut p, &mut movie);
+            let chip = run(&mut rec, 7);
+            rec.finish();
+            chip
+        };
+        assert_eq!(movie.frames, vec![1 << 5, 1 << 5, 0]);
+
+        let movie = Movie::parse(&movie.to_bytes()).unwrap();
+        let mut p = Mock::default();
+        let mut player = Player::new(&mut p, &movie);
+        let played = run(&mut player, movie.seed);
+        assert_eq!(played.regs(), recorded.regs());
+        assert_eq!(played.regs()[2], 5);
+        assert!(!player.is_finished(), "exited within the last frame");
+    }
+
+    #[test]
+    fn key_out_of_keypad() {
+        // Loop until VX = 0xF5, key 5, is down, then exit.
+        let rom = [0x60, 0xF5, 0xE0, 0x9E, 0x12, 0x02, 0x00, 0xFD];
+        let mut movie = Movie::new(::rom_hash(&rom), 0, Quirks::VIP, 0x200, 3);
+        let mut p = Mock::default();
+        p.script = vec![0, 1 << 5];
+        let mut chip = Chip::default();
+        chip.load(0x200, &rom);
+        {
+            let mut rec = Recorder::new(&mut p, &mut movie);
+            while chip.frame(3, &mut rec).unwrap() == Status::Running {}
+            rec.finish();
+        }
+        assert_eq!(movie.frames, vec![0, 0, 1 << 5]);
+
+        let mut p = Mock::default();
+        let mut player = Player::new(&mut p, &movie);
+        let mut chip = Chip::default();
+        chip.load(0x200, &rom);
+        let mut frames = 1;
+        while chip.frame(3, &mut player).unwrap() == Status::Running { frames += 1 }
+        assert_eq!(frames, movie.frames.len());
+    }
+
+    #[test]
+    fn parse() {
+        let movie = Movie::new(1, 2, Quirks::SCHIP, 0x300, 1000);
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::parse(&bytes), Ok(movie));
+        assert_eq!(Movie::parse(&bytes[1..]), Err(Error::Magic));
+        assert_eq!(Movie::parse(&bytes[..bytes.len() - 1]), Err(Error::Truncated));
+        let none = Movie::new(1, 2, Quirks::SCHIP, 0x300, 0).to_bytes();
+        assert_eq!(Movie::parse(&none), Err(Error::Truncated), "0 cycles per frame");
+    }
+}
+
+pub const MAGIC: &[u8; 4] = b"C8MV";
+/// Incremented on any change of the format.
+pub const VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Not a movie.
+    Magic,
+    /// Saved in an unsupported version of the format.
+    Version(u16),
+    /// Cut short, or with out of range values.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Magic => write!(f, "not a movie"),
+            Error::Version(v) => write!(f, "unsupported movie version {}, expected {}", v, VERSION),
+            Error::Truncated => write!(f, "corrupted movie"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// Hash of the ROM, see `rom_hash`.
+    pub rom: u64,
+    /// Seed of the `XorShift` of `CXNN`.
+    pub seed: u64,
+    pub quirks: Quirks,
+    /// Where the ROM was loaded and started.
+    pub load: u16,
+    pub cycles: u32,
+    /// Keys down in each frame, bit N for key N.
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Without frames, to record.
+    pub fn new(rom: u64, seed: u64, quirks: Quirks, load: u16, cycles: u32) -> Movie {
+        Movie { rom, seed, quirks, load, cycles, frames: vec![] }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        bytes::push(&mut out, VERSION as u64, 2);
+        bytes::push(&mut out, self.rom, 8);
+        bytes::push(&mut out, self.seed, 8);
+        out.extend_from_slice(&self.quirks.to_bytes());
+        bytes::push(&mut out, self.load as u64, 2);
+        bytes::push(&mut out, self.cycles as u64, 4);
+        bytes::push(&mut out, self.frames.len() as u64, 4);
+        for &keydowns in &self.frames {
+            bytes::push(&mut out, keydowns as u64, 2);
+        }
+        out
+    }
+
+    pub fn parse(data: &[u8]) -> result::Result<Movie, Error> {
+        let mut r = Reader::new(data, Error::Truncated);
+        if r.bytes(4).ok() != Some(&MAGIC[..]) { return Err(Error::Magic) }
+        match r.u16()? {
+            VERSION => (),
+            v => return Err(Error::Version(v)),
+        }
+        let rom = r.u64()?;
+        let seed = r.u64()?;
+        let mut quirks = [0; 6];
+        r.array(&mut quirks)?;
+        let quirks = Quirks::from_bytes(&quirks).ok_or(Error::Truncated)?;
+        let load = r.u16()?;
+        let cycles = r.u32()?;
+        // Nothing would ever run.
+        if cycles == 0 { return Err(Error::Truncated) }
+        let mut movie = Movie::new(rom, seed, quirks, load, cycles);
+        for _ in 0..r.u32()? {
+            movie.frames.push(r.u16()?);
+        }
+        if !r.is_empty() { return Err(Error::Truncated) }
+        Ok(movie)
+    }
+}
+
+/// Keys down on `p`.
+fn keydowns<P: Input>(p: &P) -> u16 {
+    (0..16).filter(|&k| p.keydown(k)).fold(0, |bits, k| bits | 1 << k)
+}
+
+/// Records the input of `p` into a movie, frame by frame.
+pub struct Recorder<'a, P: 'a> {
+    p: &'a mut P,
+    movie: &'a mut Movie,
+    /// Keys down as the current frame began.
+    keydowns: u16,
+}
+
+impl<'a, P: Input> Recorder<'a, P> {
+    pub fn new(p: &'a mut P, movie: &'a mut Movie) -> Recorder<'a, P> {
+        let keydowns = keydowns(p);
+        Recorder { p, movie, keydowns }
+    }
+
+    /// Keep the input of the current frame, for a run ended within it.
+    pub fn finish(self) {
+        self.movie.frames.push(self.keydowns);
+    }
+}
+
+impl<'a, P: Timer + Input> Timer for Recorder<'a, P> {
+    fn pump(&mut self) -> bool {
+        let quit = self.p.pump();
+        self.movie.frames.push(self.keydowns);
+        self.keydowns = keydowns(self.p);
+        quit
+    }
+}
+
+impl<'a, P> Input for Recorder<'a, P> {
+    /// As when the frame began, like what is played back.
+    fn keydown(&self, which: usize) -> bool {
+        self.keydowns & 1 << which != 0
+    }
+}
+
+/// Plays a movie back as the input, the rest going to `p`.
+pub struct Player<'a, P: 'a> {
+    p: &'a mut P,
+    movie: &'a Movie,
+    /// Index of the current frame.
+    frame: usize,
+}
+
+impl<'a, P> Player<'a, P> {
+    pub fn new(p: &'a mut P, movie: &'a Movie) -> Player<'a, P> {
+        Player { p, movie, frame: 0 }
+    }
+
+    /// All the frames have been played.
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+impl<'a, P: Timer> Timer for Player<'a, P> {
+    /// Also requests to quit at the end of the movie.
+    fn pump(&mut self) -> bool {
+        let quit = self.p.pump();
+        self.frame += 1;
+        quit || self.is_finished()
+    }
+}
+
+impl<'a, P> Input for Player<'a, P> {
+    fn keydown(&self, which: usize) -> bool {
+        self.movie.frames.get(self.frame).is_some_and(|&keys| keys & 1 << which != 0)
+    }
+}
+
+macro_rules! forward_output {
+    ($wrapper:ident) => {
+        impl<'a, P: Video> Video for $wrapper<'a, P> {
+            fn present(&mut self, screen: &Framebuffer) { self.p.present(screen) }
+        }
+
+        impl<'a, P: Audio> Audio for $wrapper<'a, P> {
+            fn buzz(&mut self, on: bool) { self.p.buzz(on) }
+            fn pattern(&mut self, pattern: &[u8; 16], pitch: u8) { self.p.pattern(pattern, pitch) }
+        }
+
+        impl<'a, P: Machine> Machine for $wrapper<'a, P> {
+            fn sys(&mut self, addr: u16) { self.p.sys(addr) }
+        }
+    };
+}
+
+forward_output!(Recorder);
+forward_output!(Player);
@@ -1,5 +1,7 @@
 //! Traits for peripherals.
 
+use framebuffer::Framebuffer;
+
 /// Synchronization.
 pub trait Timer {
     /// Wait for the next V-Sync (60 Hz).
@@ -7,29 +9,36 @@ pub trait Timer {
     fn pump(&mut self) -> bool;
 }
 
-/// Graphics controller.
+/// Graphics controller. The chip draws sprites into its `Framebuffer`
+/// itself, so the peripheral only shows it.
 pub trait Video {
-    /// Clears the screen.
-    fn clear(&mut self);
-    /// Draw sprite by flipping color.
-    /// Returns `true` if anything has been flipped to `false`.
-    fn draw(&mut self, x: isize, y: isize, sprite: &[u8]) -> bool;
-    /// Present the screen to display.
-    fn present(&self);
+    /// Show `screen`, as drawn by the end of a frame. Each pixel is in
+    /// one of four colors: the bits of its XO-CHIP planes combined, bit 0
+    /// for the first plane.
+    fn present(&mut self, screen: &Framebuffer);
 }
 
 /// Sound controller.
 pub trait Audio {
     /// Enable or disable buzzer
     fn buzz(&mut self, on: bool);
+    /// Buzz with XO-CHIP `pattern` from now on: 128 1-bit samples, most
+    /// significant bit first, played in a loop at
+    /// `4000 * 2 ^ ((pitch - 64) / 48)` samples per second.
+    fn pattern(&mut self, pattern: &[u8; 16], pitch: u8);
 }
 
 /// Input state controller.
 pub trait Input {
-    /// Determine if key is pressed down (`true`) or not (`false`)
+    /// Determine if key is pressed down (`true`) or not (`false`).
+    /// `FX0A` polls it too, once a frame while waiting. `which` is always
+    /// a key of the keypad, `0` ~ `F`.
     fn keydown(&self, which: usize) -> bool;
-    /// Wait until any key is pressed.
-    /// Returns `None` if quit-requested.
-    fn key(&self) -> Option<u8>;
 }
 
+/// Host machine.
+pub trait Machine {
+    /// Call the native machine code routine at `addr` (`0NNN`).
+    /// Most hosts have no such code and simply ignore it.
+    fn sys(&mut self, addr: u16);
+}
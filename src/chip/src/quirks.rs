@@ -0,0 +1,146 @@
+//! Behaviors that differ between CHIP-8 interpreters.
+//!
+//! The original COSMAC VIP interpreter left a few things to chance that
+//! later interpreters (CHIP-48, SUPER-CHIP) did differently, and programs
+//! written for one platform often rely on its behavior.
+
+/// How `FX55` and `FX65` change `I`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    /// `I` is left untouched (SUPER-CHIP).
+    Keep,
+    /// `I += X` (CHIP-48).
+    AddX,
+    /// `I += X + 1`, pointing past the last byte accessed (COSMAC VIP).
+    AddXPlusOne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VY into VX, instead of shifting VX in place.
+    pub shift_vy: bool,
+    /// `FX55`/`FX65` increment of `I`.
+    pub load_store: LoadStore,
+    /// `BNNN` jumps to `NNN + VX`, X being the highest nibble of NNN,
+    /// instead of `NNN + V0`.
+    pub jump_vx: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping
+    /// around. The position of a sprite always wraps.
+    pub clip: bool,
+    /// `DXYN` waits for the next frame, so at most one sprite is drawn
+    /// per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// What this emulator did before quirks could be chosen, the
+    /// default: shifts of VY, `I` left untouched, `BNNN` from V0 and
+    /// sprites wrapping around.
+    pub const LEGACY: Quirks = Quirks {
+        shift_vy: true,
+        load_store: LoadStore::Keep,
+        jump_vx: false,
+        vf_reset: false,
+        clip: false,
+        display_wait: false,
+    };
+
+    /// The original interpreter on the COSMAC VIP.
+    pub const VIP: Quirks = Quirks {
+        shift_vy: true,
+        load_store: LoadStore::AddXPlusOne,
+        jump_vx: false,
+        vf_reset: true,
+        clip: true,
+        display_wait: true,
+    };
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub const CHIP48: Quirks = Quirks {
+        shift_vy: false,
+        load_store: LoadStore::AddX,
+        jump_vx: true,
+        vf_reset: false,
+        clip: true,
+        display_wait: false,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP-48 calculators.
+    pub const SCHIP: Quirks = Quirks {
+        shift_vy: false,
+        load_store: LoadStore::Keep,
+        jump_vx: true,
+        vf_reset: false,
+        clip: true,
+        display_wait: false,
+    };
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const XOCHIP: Quirks = Quirks {
+        shift_vy: true,
+        load_store: LoadStore::AddXPlusOne,
+        jump_vx: false,
+        vf_reset: false,
+        clip: false,
+        display_wait: false,
+    };
+
+    /// Presets by name, for hosts to choose from.
+    pub const PRESETS: &'static [(&'static str, Quirks)] = &[
+        ("legacy", Quirks::LEGACY),
+        ("vip", Quirks::VIP),
+        ("chip48", Quirks::CHIP48),
+        ("schip", Quirks::SCHIP),
+        ("xochip", Quirks::XOCHIP),
+    ];
+
+    /// Look up a preset by its name in `PRESETS`.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        Quirks::PRESETS.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, q)| q)
+    }
+
+    /// One byte each of `shift_vy`, `load_store` (`0` keep, `1` add X,
+    /// `2` add X + 1), `jump_vx`, `vf_reset`, `clip` and `display_wait`,
+    /// booleans as `0` or `1`.
+    pub fn to_bytes(&self) -> [u8; 6] {
+        let load_store = match self.load_store {
+            LoadStore::Keep => 0,
+            LoadStore::AddX => 1,
+            LoadStore::AddXPlusOne => 2,
+        };
+        [self.shift_vy as u8, load_store, self.jump_vx as u8, self.vf_reset as u8,
+         self.clip as u8, self.display_wait as u8]
+    }
+
+    /// The quirks of `to_bytes`, unless invalid.
+    pub fn from_bytes(bytes: &[u8; 6]) -> Option<Quirks> {
+        let flag = |b: u8| match b {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        };
+        Some(Quirks {
+            shift_vy: flag(bytes[0])?,
+            load_store: match bytes[1] {
+                0 => LoadStore::Keep,
+                1 => LoadStore::AddX,
+                2 => LoadStore::AddXPlusOne,
+                _ => return None,
+            },
+            jump_vx: flag(bytes[2])?,
+            vf_reset: flag(bytes[3])?,
+            clip: flag(bytes[4])?,
+            display_wait: flag(bytes[5])?,
+        })
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::LEGACY
+    }
+}
@@ -1,71 +1,132 @@
 //! Ram implementation for the chip.
 //!
-//! Support writing and reading various sized native types.
+//! Support writing various sized native types, and reading slices and
+//! instruction words.
 //! Does endianess conversion since chip-8 is big-endian machine.
+//! Accesses running past the end of RAM fail with `OutOfRange`.
+//! Accesses under a watchpoint are recorded as hits.
 
-use std::mem::transmute;
-
-pub trait Read<T> {
-    fn read(&mut self, addr: usize) -> T;
-}
+use std::collections::VecDeque;
+use std::mem;
+use watch::{Access, Hit, Watchpoint, MAX_HITS};
 
 pub trait Write<T> {
-    fn write(&mut self, addr: usize, value: T);
+    fn write(&mut self, addr: usize, value: T) -> Result<(), OutOfRange>;
 }
 
+/// Reads `num` values at once.
 pub trait Slice<T> {
     /// num is number of T, not number of bytes
-    fn slice(&mut self, start: usize, num: usize) -> &mut [T];
+    fn slice(&mut self, start: usize, num: usize) -> Result<&[T], OutOfRange>;
+}
+
+/// `len` bytes starting from `start` do not fit into RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange {
+    pub start: usize,
+    pub len: usize,
 }
 
-const RAM_SIZE: usize = 0x10000;
+pub const RAM_SIZE: usize = 0x10000;
 pub struct Ram {
     mem: [u8; RAM_SIZE],
+    watchpoints: Vec<Watchpoint>,
+    hits: VecDeque<Hit>,
+    /// Address of the instruction accessing RAM, for the hits.
+    pc: u16,
+}
+
+impl Ram {
+    fn range(&mut self, start: usize, len: usize) -> Result<&mut [u8], OutOfRange> {
+        match start.checked_add(len) {
+            Some(end) if end <= RAM_SIZE => Ok(&mut self.mem[start..end]),
+            _ => Err(OutOfRange { start, len }),
+        }
+    }
+
+    /// Up to `len` bytes from `start`, fewer at the end of RAM.
+    pub fn peek(&self, start: usize, len: usize) -> &[u8] {
+        let start = start.min(RAM_SIZE);
+        &self.mem[start..start.saturating_add(len).min(RAM_SIZE)]
+    }
+
+    /// Fetch the instruction word at `addr`, executed by the instruction
+    /// at `pc`.
+    pub fn fetch(&mut self, addr: usize, pc: u16) -> Result<u16, OutOfRange> {
+        self.pc = pc;
+        let word = self.range(addr, 2).map(|m| (m[0] as u16) << 8 | m[1] as u16)?;
+        self.notify(addr, 2, Access::Execute);
+        Ok(word)
+    }
+
+    /// Replace the whole RAM with `mem`, without reporting it.
+    pub fn restore(&mut self, mem: &[u8]) {
+        self.mem.copy_from_slice(mem);
+    }
+
+    pub fn watchpoints(&mut self) -> &mut Vec<Watchpoint> {
+        &mut self.watchpoints
+    }
+
+    /// Hits since last taken, oldest first.
+    pub fn take_hits(&mut self) -> Vec<Hit> {
+        mem::take(&mut self.hits).into()
+    }
+
+    /// Record hits for `len` bytes from `start`, after they were accessed.
+    fn notify(&mut self, start: usize, len: usize, access: Access) {
+        for addr in start..start + len {
+            for w in self.watchpoints.iter().filter(|w| w.matches(addr, access)) {
+                if self.hits.len() == MAX_HITS {
+                    self.hits.pop_front();
+                }
+                self.hits.push_back(Hit {
+                    pc: self.pc,
+                    addr: addr as u16,
+                    access,
+                    value: self.mem[addr],
+                    action: w.action,
+                });
+            }
+        }
+    }
 }
 
 impl Default for Ram {
     fn default() -> Ram {
         Ram {
             mem: [0; RAM_SIZE],
+            watchpoints: Vec::new(),
+            hits: VecDeque::new(),
+            pc: 0,
         }
     }
 }
 
-impl Read<u8> for Ram {
-    fn read(&mut self, addr: usize) -> u8 {
-        self.mem[addr]
-    }
-}
-
-impl Read<u16> for Ram {
-    fn read(&mut self, addr: usize) -> u16 {
-        let be: &u16 = unsafe { transmute(&self.mem[addr]) };
-        u16::from_be(*be)
+impl Write<&[u8]> for Ram {
+    fn write(&mut self, addr: usize, values: &[u8]) -> Result<(), OutOfRange> {
+        self.range(addr, values.len())?.copy_from_slice(values);
+        self.notify(addr, values.len(), Access::Write);
+        Ok(())
     }
 }
 
 impl Write<u8> for Ram {
-    fn write(&mut self, addr: usize, value: u8) {
-        self.mem[addr] = value;
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), OutOfRange> {
+        self.write(addr, &[value][..])
     }
 }
 
 impl Write<u16> for Ram {
-    fn write(&mut self, addr: usize, value: u16) {
-        let be: &mut u16 = unsafe { transmute(&mut self.mem[addr]) };
-        *be = value.to_be();
+    fn write(&mut self, addr: usize, value: u16) -> Result<(), OutOfRange> {
+        self.write(addr, &[(value >> 8) as u8, value as u8][..])
     }
 }
 
 impl Slice<u8> for Ram {
-    fn slice(&mut self, start: usize, num: usize) -> &mut [u8] {
-        &mut self.mem[start..start+num*1]
+    fn slice(&mut self, start: usize, num: usize) -> Result<&[u8], OutOfRange> {
+        self.range(start, num)?;
+        self.notify(start, num, Access::Read);
+        Ok(&self.mem[start..start + num])
     }
 }
-
-impl Slice<u16> for Ram {
-    fn slice(&mut self, start: usize, num: usize) -> &mut [u16] {
-        unsafe { transmute(&mut self.mem[start..start+num*2]) }
-    }
-}
-
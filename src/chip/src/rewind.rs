@@ -0,0 +1,153 @@
+//! Ring buffer of save states to go back in time.
+//!
+//! Only the newest state is kept whole. Each older one is kept as the
+//! bytes differing from the state after it, since most of RAM does not
+//! change from one frame to the next.
+
+use std::collections::VecDeque;
+use bytes::{self, Reader};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn push_and_pop() {
+        let mut r = Rewind::new(3);
+        let states = [vec![1; 1000], vec![1, 2, 3], vec![5; 2000], vec![5; 2001]];
+        for (t, s) in states.iter().enumerate() {
+            r.push(t as u64 * 10, s.clone());
+        }
+        assert_eq!(r.len(), 3, "the oldest is dropped");
+        assert_eq!(r.pop(), Some((30, states[3].clone())));
+        assert_eq!(r.pop(), Some((20, states[2].clone())));
+        assert_eq!(r.pop(), Some((10, states[1].clone())));
+        assert_eq!(r.pop(), None);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn small_delta() {
+        let old: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let mut new = old.clone();
+        new[10] = 0;
+        new[4000] = 0;
+        let d = delta(&old, &new);
+        assert!(d.len() < 32, "{} bytes", d.len());
+        assert_eq!(undo(&new, &d), old);
+        assert_eq!(undo(&old[..10], &delta(&old[..20], &old[..10])), &old[..20]);
+    }
+
+    #[test]
+    fn seek() {
+        let mut r = Rewind::new(10);
+        for t in 0..5 {
+            r.push(t * 100, vec![t as u8; 10]);
+        }
+        assert_eq!(r.seek(250), Some((200, &[2; 10][..])));
+        assert_eq!(r.len(), 3);
+        assert_eq!(r.seek(200).map(|s| s.0), Some(200));
+        assert_eq!(r.seek(50).map(|s| s.0), Some(0));
+    }
+}
+
+/// Unchanged bytes between changed ones, up to this many, are kept in
+/// the delta rather than starting another run.
+const GAP: usize = 8;
+
+pub struct Rewind {
+    capacity: usize,
+    /// The newest state, with when it was taken.
+    newest: Option<(u64, Vec<u8>)>,
+    /// Older states as deltas, oldest first.
+    deltas: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` states.
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind { capacity, newest: None, deltas: VecDeque::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.newest.iter().count() + self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    /// Keep `state`, taken at time `at`, e.g. in executed instructions.
+    pub fn push(&mut self, at: u64, state: Vec<u8>) {
+        if self.capacity == 0 { return }
+        if let Some((t, old)) = self.newest.take() {
+            self.deltas.push_back((t, delta(&old, &state)));
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some((at, state));
+    }
+
+    /// Take the newest state, with when it was taken.
+    pub fn pop(&mut self) -> Option<(u64, Vec<u8>)> {
+        let (at, state) = self.newest.take()?;
+        if let Some((t, d)) = self.deltas.pop_back() {
+            self.newest = Some((t, undo(&state, &d)));
+        }
+        Some((at, state))
+    }
+
+    /// Drop the states taken after `at`, and return the newest one left.
+    pub fn seek(&mut self, at: u64) -> Option<(u64, &[u8])> {
+        while self.newest.as_ref().is_some_and(|s| s.0 > at) {
+            self.pop();
+        }
+        self.newest.as_ref().map(|&(t, ref s)| (t, &s[..]))
+    }
+}
+
+/// What turns `new` back into `old`: the length of `old`, then runs of
+/// the bytes of `old` differing from `new`, each as offset, length and
+/// the bytes.
+fn delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let differs = |i: usize| new.get(i) != Some(&old[i]);
+    let mut out = vec![];
+    bytes::push(&mut out, old.len() as u64, 4);
+    let mut i = 0;
+    while i < old.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i + 1;
+        while end < old.len() && (end..old.len().min(end + GAP)).any(differs) {
+            end += 1;
+        }
+        bytes::push(&mut out, start as u64, 4);
+        bytes::push(&mut out, (end - start) as u64, 4);
+        out.extend_from_slice(&old[start..end]);
+        i = end;
+    }
+    out
+}
+
+/// # Panics
+/// If `delta` is not one made by `delta`.
+fn undo(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut r = Reader::new(delta, ());
+    let mut old = new.to_vec();
+    old.resize(r.u32().unwrap() as usize, 0);
+    while !r.is_empty() {
+        let start = r.u32().unwrap() as usize;
+        let len = r.u32().unwrap() as usize;
+        old[start..start + len].copy_from_slice(r.bytes(len).unwrap());
+    }
+    old
+}
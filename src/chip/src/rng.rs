@@ -0,0 +1,90 @@
+//! Random numbers for `CXNN`, reproducible from a seed so that runs can
+//! be replayed.
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bytes(rng: &mut dyn Rng, n: usize) -> Vec<u8> {
+        (0..n).map(|_| rng.next_u8()).collect()
+    }
+
+    #[test]
+    fn seed() {
+        let a = bytes(&mut XorShift::new(1), 64);
+        assert_eq!(a, bytes(&mut XorShift::new(1), 64));
+        assert_ne!(a, bytes(&mut XorShift::new(2), 64));
+        assert_ne!(bytes(&mut XorShift::new(0), 64), vec![0; 64]);
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let mut rng = XorShift::new(42);
+        bytes(&mut rng, 10);
+        let state = rng.save();
+        let next = bytes(&mut rng, 10);
+
+        let mut other = XorShift::default();
+        assert!(other.restore(&state));
+        assert_eq!(bytes(&mut other, 10), next);
+        assert!(!other.restore(&[1, 2, 3]));
+        assert!(!other.restore(&[0; 8]));
+    }
+}
+
+/// A source of random bytes, pluggable into `Chip::set_rng`.
+pub trait Rng {
+    fn next_u8(&mut self) -> u8;
+
+    /// The state, to continue the same sequence after `restore`.
+    fn save(&self) -> Vec<u8>;
+
+    /// Continue from a `save`d state. Returns false, changing nothing,
+    /// if it is not a valid state.
+    fn restore(&mut self, state: &[u8]) -> bool;
+}
+
+/// xorshift64*: fast, and good enough for games.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XorShift {
+    /// Never zero.
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        // splitmix64, so that close seeds give unrelated sequences.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        XorShift { state: if z == 0 { 1 } else { z } }
+    }
+}
+
+impl Default for XorShift {
+    /// Always seeded with 0, for reproducible runs.
+    fn default() -> XorShift {
+        XorShift::new(0)
+    }
+}
+
+impl Rng for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn save(&self) -> Vec<u8> {
+        (0..8).rev().map(|i| (self.state >> (8 * i)) as u8).collect()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> bool {
+        let s = state.iter().fold(0, |s, &b| s << 8 | b as u64);
+        if state.len() != 8 || s == 0 { return false }
+        self.state = s;
+        true
+    }
+}
@@ -0,0 +1,276 @@
+//! Save states: the whole machine in a versioned binary format.
+//!
+//! Numbers are big-endian, booleans are one byte of `0` or `1`.
+//!
+//! ```text
+//! size    content
+//! 4       "C8ST"
+//! 2       VERSION
+//! 8       ROM hash, see `rom_hash`
+//! 6       quirks, see `Quirks::to_bytes`
+//! 2       PC
+//! 2       I
+//! 16      V0 ~ VF
+//! 1       delay timer
+//! 1       sound timer
+//! 1       stack depth N
+//! 2N      stack, outermost first
+//! 1       hires
+//! 1       selected planes
+//! 1       screen width W
+//! 1       screen height H
+//! W*H     planes of each pixel, row by row
+//! 16      audio pattern
+//! 1       pitch
+//! 1       waiting for a key in `FX0A`
+//! 2       keys seen down while waiting, bit N for key N
+//! 1       random number generator state length N
+//! N       random number generator state, see `Rng::save`
+//! 16      RPL flags
+//! 2       font address
+//! 80      font glyphs
+//! 2       big font address
+//! 160     big font glyphs
+//! 65536   RAM
+//! 4       peripheral data length N
+//! N       peripheral data, defined by the host
+//! ```
+//!
+//! A state only loads with the same ROM and quirks it was saved with.
+//! Watchpoints are not part of a state.
+
+use std::error;
+use std::fmt;
+use std::mem;
+use std::num::Wrapping;
+use std::result;
+use bytes::{self, Reader};
+use font::{Font, GLYPH_SIZE, BIG_GLYPH_SIZE};
+use framebuffer::Framebuffer;
+use ram::RAM_SIZE;
+use {Chip, RPL_FLAGS, STACK_DEPTH};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test::Mock;
+    use rng::{Rng, XorShift};
+    use quirks::Quirks;
+
+    const ROM: &[u8] = &[0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xEE];
+
+    fn setup() -> Chip {
+        let mut chip = Chip::default();
+        chip.load(0x200, ROM);
+        chip.screen.draw(3, 60, 30, &[0xFF], false);
+        for _ in 0..4 {
+            chip.cycle(&mut Mock::default()).unwrap();
+        }
+        chip
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut chip = setup();
+        let hash = rom_hash(ROM);
+        let state = chip.save_state(hash, b"screen");
+        assert_eq!(&state[..4], MAGIC);
+
+        let saved = (chip.pc(), chip.reg_i(), chip.regs(), chip.stack().to_vec());
+        chip.reset();
+        assert_eq!(chip.load_state(&state, hash), Ok(b"screen".to_vec()));
+        assert_eq!((chip.pc(), chip.reg_i(), chip.regs(), chip.stack().to_vec()), saved);
+        assert_eq!(chip.memory(0x300, 1), &[5]);
+        assert_eq!(chip.screen().pixels().iter().filter(|&&px| px == 3).count(), 8);
+        assert_eq!(chip.save_state(hash, b"screen"), state);
+        let mut rng = XorShift::default();
+        assert_eq!(chip.rng.next_u8(), rng.next_u8(), "the sequence continues");
+    }
+
+    #[test]
+    fn reject() {
+        let mut chip = setup();
+        let hash = rom_hash(ROM);
+        let state = chip.save_state(hash, &[]);
+        assert_eq!(chip.load_state(&state, rom_hash(&[0x00, 0xE0])), Err(Error::Rom));
+        assert_eq!(chip.load_state(&state[..100], hash), Err(Error::Truncated));
+        assert_eq!(chip.load_state(&state[1..], hash), Err(Error::Magic));
+
+        let mut newer = state.clone();
+        newer[5] += 1;
+        assert_eq!(chip.load_state(&newer, hash), Err(Error::Version(VERSION + 1)));
+
+        chip.set_quirks(Quirks::SCHIP);
+        assert_eq!(chip.load_state(&state, hash), Err(Error::Quirks));
+        // Nothing changed by the failed attempts.
+        assert_eq!(chip.pc(), 0x20A);
+    }
+
+    #[test]
+    fn reject_screen() {
+        let chip = setup();
+        let hash = rom_hash(ROM);
+        let state = chip.save_state(hash, &[]);
+        // Hires flag, planes, width and height after a stack of 1.
+        let at = 45;
+        assert_eq!(state[at..at + 4], [0, 1, 64, 32]);
+        let load = |state: &[u8]| Chip::default().load_state(state, hash).map(|_| ());
+
+        let mut empty = state[..at + 2].to_vec();
+        empty.extend(&[0, 0]);
+        empty.extend(&state[at + 4 + 64 * 32..]);
+        assert_eq!(load(&empty), Err(Error::Truncated), "0x0 screen");
+        let mut hires = state.clone();
+        hires[at] = 1;
+        assert_eq!(load(&hires), Err(Error::Truncated), "lores screen in hires");
+        let mut pixel = state.clone();
+        pixel[at + 4] = 4;
+        assert_eq!(load(&pixel), Err(Error::Truncated));
+        assert_eq!(load(&state), Ok(()));
+    }
+}
+
+pub const MAGIC: &[u8; 4] = b"C8ST";
+/// Incremented on any change of the format.
+pub const VERSION: u16 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Not a save state.
+    Magic,
+    /// Saved in an unsupported version of the format.
+    Version(u16),
+    /// Saved with another ROM.
+    Rom,
+    /// Saved with other quirks.
+    Quirks,
+    /// Cut short, or with out of range values, including a random
+    /// number generator state not fitting the current generator.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Magic => write!(f, "not a save state"),
+            Error::Version(v) => write!(f, "unsupported save state version {}, expected {}", v, VERSION),
+            Error::Rom => write!(f, "save state of another ROM"),
+            Error::Quirks => write!(f, "save state with other quirks"),
+            Error::Truncated => write!(f, "corrupted save state"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// FNV-1a hash identifying a ROM image.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl Chip {
+    /// Serialize the machine, with `peripheral` data to restore along,
+    /// for the ROM hashed to `rom`.
+    pub fn save_state(&self, rom: u64, peripheral: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        let u16 = |out: &mut Vec<u8>, v: u16| bytes::push(out, v as u64, 2);
+        u16(&mut out, VERSION);
+        bytes::push(&mut out, rom, 8);
+        out.extend_from_slice(&self.quirks.to_bytes());
+        u16(&mut out, self.pc.0);
+        u16(&mut out, self.reg_i.0);
+        out.extend(self.regs.iter().map(|r| r.0));
+        out.push(self.reg_delay);
+        out.push(self.reg_sound);
+        out.push(self.stack.len() as u8);
+        for &addr in &self.stack {
+            u16(&mut out, addr);
+        }
+        out.push(self.hires as u8);
+        out.push(self.planes);
+        out.push(self.screen.width() as u8);
+        out.push(self.screen.height() as u8);
+        out.extend_from_slice(self.screen.pixels());
+        out.extend_from_slice(&self.pattern);
+        out.push(self.pitch);
+        out.push(self.key_wait.is_some() as u8);
+        u16(&mut out, self.key_wait.unwrap_or(0));
+        let rng = self.rng.save();
+        out.push(rng.len() as u8);
+        out.extend(rng);
+        out.extend_from_slice(&self.rpl);
+        u16(&mut out, self.font.addr);
+        out.extend(self.font.bytes());
+        u16(&mut out, self.font.big_addr);
+        out.extend(self.font.big_bytes());
+        out.extend_from_slice(self.ram.peek(0, RAM_SIZE));
+        bytes::push(&mut out, peripheral.len() as u64, 4);
+        out.extend_from_slice(peripheral);
+        out
+    }
+
+    /// Restore a state saved by `save_state` for the ROM hashed to `rom`,
+    /// returning its peripheral data. The current quirks must be the
+    /// saved ones, and the current random number generator must accept
+    /// the saved state. On error, nothing is changed.
+    pub fn load_state(&mut self, state: &[u8], rom: u64) -> result::Result<Vec<u8>, Error> {
+        let mut r = Reader::new(state, Error::Truncated);
+        if r.bytes(4).ok() != Some(&MAGIC[..]) { return Err(Error::Magic) }
+        match r.u16()? {
+            VERSION => (),
+            v => return Err(Error::Version(v)),
+        }
+        if r.u64()? != rom { return Err(Error::Rom) }
+        if r.bytes(6)? != self.quirks.to_bytes() { return Err(Error::Quirks) }
+
+        let mut chip = Chip { quirks: self.quirks, ..Default::default() };
+        chip.pc = Wrapping(r.u16()?);
+        chip.reg_i = Wrapping(r.u16()?);
+        for reg in chip.regs.iter_mut() {
+            *reg = Wrapping(r.u8()?);
+        }
+        chip.reg_delay = r.u8()?;
+        chip.reg_sound = r.u8()?;
+        let depth = r.u8()? as usize;
+        if depth > STACK_DEPTH { return Err(Error::Truncated) }
+        for _ in 0..depth {
+            chip.stack.push(r.u16()?);
+        }
+        chip.hires = r.bool()?;
+        chip.planes = r.u8()?;
+        let (w, h) = (r.u8()? as usize, r.u8()? as usize);
+        if (w, h) != chip.screen_size() { return Err(Error::Truncated) }
+        let pixels = r.bytes(w * h)?;
+        if pixels.iter().any(|&px| px > 3) { return Err(Error::Truncated) }
+        chip.screen = Framebuffer::from_pixels(w, h, pixels.to_vec()).ok_or(Error::Truncated)?;
+        r.array(&mut chip.pattern)?;
+        chip.pitch = r.u8()?;
+        let waiting = r.bool()?;
+        let pressed = r.u16()?;
+        chip.key_wait = if waiting { Some(pressed) } else { None };
+        let len = r.u8()? as usize;
+        let rng = r.bytes(len)?;
+        let mut rpl = [0; RPL_FLAGS];
+        r.array(&mut rpl)?;
+        chip.rpl = rpl;
+        chip.font = Font::default();
+        chip.font.addr = r.u16()?;
+        for glyph in chip.font.glyphs.iter_mut() {
+            r.array(&mut glyph[..GLYPH_SIZE])?;
+        }
+        chip.font.big_addr = r.u16()?;
+        for glyph in chip.font.big_glyphs.iter_mut() {
+            r.array(&mut glyph[..BIG_GLYPH_SIZE])?;
+        }
+        chip.ram.restore(r.bytes(RAM_SIZE)?);
+        let len = r.u32()? as usize;
+        let peripheral = r.bytes(len)?.to_vec();
+
+        // The current generator, continuing from the saved state.
+        if !self.rng.restore(rng) { return Err(Error::Truncated) }
+        mem::swap(&mut chip.rng, &mut self.rng);
+        *chip.ram.watchpoints() = self.ram.watchpoints().clone();
+        *self = chip;
+        Ok(peripheral)
+    }
+}
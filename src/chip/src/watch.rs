@@ -0,0 +1,78 @@
+//! Watchpoints on ranges of RAM, reporting each access by the program,
+//! e.g. to find where a game keeps its score or which instruction
+//! modifies the code.
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let mut w = Watchpoint::new(0x300, 0x301);
+        assert!(w.matches(0x300, Access::Read));
+        assert!(w.matches(0x301, Access::Execute));
+        assert!(!w.matches(0x302, Access::Write));
+        assert!(!w.matches(0x2FF, Access::Write));
+
+        w.read = false;
+        assert!(!w.matches(0x300, Access::Read));
+        assert!(w.matches(0x300, Access::Write));
+    }
+}
+
+/// At most this many hits are kept until taken; older ones are dropped.
+pub const MAX_HITS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Fetched as (part of) an instruction.
+    Execute,
+}
+
+/// What to do on a hit, besides reporting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Pause the debugger after the accessing instruction.
+    Break,
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// First and last address watched.
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub action: Action,
+}
+
+impl Watchpoint {
+    /// Break on any access from `start` to `end`, inclusive.
+    pub fn new(start: u16, end: u16) -> Watchpoint {
+        Watchpoint { start, end, read: true, write: true, execute: true, action: Action::Break }
+    }
+
+    pub fn matches(&self, addr: usize, access: Access) -> bool {
+        self.start as usize <= addr && addr <= self.end as usize && match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+/// A byte accessed under a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    /// Address of the accessing instruction.
+    pub pc: u16,
+    pub addr: u16,
+    pub access: Access,
+    /// The byte read, or written.
+    pub value: u8,
+    pub action: Action,
+}
@@ -0,0 +1,143 @@
+//! A peripheral without a terminal or sound device, for running ROMs in
+//! tests and batch jobs: frames go by as fast as they run, input comes
+//! from a `Script` and the presented screen is kept in memory.
+
+// Tests live at the top of each file.
+#![allow(clippy::items_after_test_module)]
+
+extern crate chip;
+
+mod script;
+pub use script::Script;
+use chip::{Framebuffer, Timer, Video, Audio, Input, Machine};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chip::{Chip, Status};
+
+    #[test]
+    fn run() {
+        // Clear, wait for key 5 to be down, then for a key to be pressed
+        // and released, draw its glyph and loop forever.
+        let rom = [0x00, 0xE0, 0x60, 0x05, 0xE0, 0x9E, 0x12, 0x04, 0xF1, 0x0A, 0xF1, 0x29, 0xD0, 0x05, 0x12, 0x0E];
+        let mut chip = Chip::default();
+        chip.load(0x200, &rom);
+        let mut p = Peripheral::new(Script::parse("3:5,6:").unwrap(), Some(10));
+        while chip.frame(10, &mut p).unwrap() == Status::Running {}
+        assert_eq!(p.frame(), 10);
+        assert_eq!(chip.regs()[1], 5);
+        let screen = p.screen();
+        let glyph: Vec<u8> = (5..10).map(|y| screen.pixel(5, y) << 3 | screen.pixel(6, y) << 2
+                                             | screen.pixel(7, y) << 1 | screen.pixel(8, y)).collect();
+        assert_eq!(glyph, [0xF, 0x8, 0xF, 0x1, 0xF]);
+    }
+
+    #[test]
+    fn image() {
+        let mut screen = Framebuffer::new(4, 2);
+        screen.draw(1, 0, 0, &[0x80], false);
+        screen.draw(2, 3, 1, &[0x80], false);
+        assert_eq!(pbm(&screen), b"P1\n4 2\n1000\n0001\n");
+    }
+
+    #[test]
+    fn wait_key() {
+        let mut chip = Chip::default();
+        chip.load(0x200, &[0xF0, 0x0A, 0x12, 0x00]);
+        let mut p = Peripheral::new(Script::parse("5:3,7:").unwrap(), None);
+        while p.frame() < 7 {
+            assert_eq!(chip.frame(10, &mut p).unwrap(), Status::Running);
+            assert!(chip.waiting_for_key());
+        }
+        chip.frame(10, &mut p).unwrap();
+        assert_eq!(chip.regs()[0], 3);
+    }
+
+    #[test]
+    fn key_out_of_keypad() {
+        // VX = 0xFF stands for key F: skips setting V1, not setting V2.
+        let rom = [0x60, 0xFF, 0xE0, 0x9E, 0x61, 0x01, 0xE0, 0xA1, 0x62, 0x02, 0x00, 0xFD];
+        let mut chip = Chip::default();
+        chip.load(0x200, &rom);
+        let mut p = Peripheral::new(Script::parse("0:f").unwrap(), None);
+        assert_eq!(chip.frame(10, &mut p).unwrap(), Status::Exited);
+        assert_eq!(chip.regs()[1..3], [0, 2]);
+    }
+}
+
+/// A plain PBM image of `screen`, black where any plane is on.
+pub fn pbm(screen: &Framebuffer) -> Vec<u8> {
+    let mut out = format!("P1\n{} {}\n", screen.width(), screen.height()).into_bytes();
+    for row in screen.pixels().chunks(screen.width()) {
+        out.extend(row.iter().map(|&px| if px != 0 { b'1' } else { b'0' }));
+        out.push(b'\n');
+    }
+    out
+}
+
+pub struct Peripheral {
+    /// As last presented.
+    screen: Framebuffer,
+    script: Script,
+    /// Frames run so far.
+    frame: u64,
+    /// Quit after this many frames.
+    frames: Option<u64>,
+    buzzing: bool,
+}
+
+impl Peripheral {
+    /// Play `script`, quitting after `frames` frames if given.
+    pub fn new(script: Script, frames: Option<u64>) -> Peripheral {
+        Peripheral { screen: Framebuffer::default(), script, frame: 0, frames, buzzing: false }
+    }
+
+    /// The screen of the last frame.
+    pub fn screen(&self) -> &Framebuffer {
+        &self.screen
+    }
+
+    /// Frames run so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_buzzing(&self) -> bool {
+        self.buzzing
+    }
+}
+
+impl Timer for Peripheral {
+    /// Does not wait.
+    fn pump(&mut self) -> bool {
+        self.frame += 1;
+        self.frames.is_some_and(|n| self.frame >= n)
+    }
+}
+
+impl Video for Peripheral {
+    fn present(&mut self, screen: &Framebuffer) {
+        self.screen.clone_from(screen);
+    }
+}
+
+impl Audio for Peripheral {
+    fn buzz(&mut self, on: bool) {
+        self.buzzing = on;
+    }
+
+    fn pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {
+    }
+}
+
+impl Input for Peripheral {
+    fn keydown(&self, which: usize) -> bool {
+        self.script.keydowns(self.frame) & 1 << which != 0
+    }
+}
+
+impl Machine for Peripheral {
+    fn sys(&mut self, _addr: u16) {
+    }
+}
@@ -0,0 +1,58 @@
+//! Scripted input: which keys are held from which frame on.
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let s = Script::parse("10:5,20:,30:a0").unwrap();
+        assert_eq!(s.keydowns(0), 0);
+        assert_eq!(s.keydowns(10), 1 << 5);
+        assert_eq!(s.keydowns(19), 1 << 5);
+        assert_eq!(s.keydowns(25), 0);
+        assert_eq!(s.keydowns(1000), 1 << 0xA | 1);
+        assert_eq!(Script::parse(""), Some(Script::default()));
+    }
+
+    #[test]
+    fn reject() {
+        assert_eq!(Script::parse("10"), None);
+        assert_eq!(Script::parse("x:5"), None);
+        assert_eq!(Script::parse("10:g"), None);
+        assert_eq!(Script::parse("20:1,10:2"), None, "out of order");
+    }
+}
+
+/// Changes of the keys held, by frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    /// Frame, then the keys held from it on, bit N for key N, in order
+    /// of frames.
+    changes: Vec<(u64, u16)>,
+}
+
+impl Script {
+    /// Parse `<frame>:<keys>` separated by commas, the keys as hex digits
+    /// held from the frame on, none if empty. Frames count from 0 and go
+    /// in order. E.g. `60:5,90:,120:4a`.
+    pub fn parse(text: &str) -> Option<Script> {
+        let mut script = Script::default();
+        for change in text.split(',').filter(|c| !c.is_empty()) {
+            let mut parts = change.splitn(2, ':');
+            let frame = parts.next()?.parse().ok()?;
+            let mut keys = 0;
+            for c in parts.next()?.chars() {
+                keys |= 1 << c.to_digit(16)?;
+            }
+            if script.changes.last().is_some_and(|&(f, _)| f > frame) { return None }
+            script.changes.push((frame, keys));
+        }
+        Some(script)
+    }
+
+    /// Keys held in `frame`, bit N for key N.
+    pub fn keydowns(&self, frame: u64) -> u16 {
+        self.changes.iter().take_while(|&&(f, _)| f <= frame).last().map_or(0, |&(_, keys)| keys)
+    }
+}
@@ -1,142 +1,494 @@
 extern crate chip;
 extern crate term_oss;
+extern crate headless;
 
-use chip::Chip;
-use term_oss::Peripheral;
-//use dummy::Peripheral;
+use chip::{Chip, Status, Quirks, Debugger, Watchpoint, Rewind, XorShift, Movie, Timer, Video, Audio, Input, Machine};
+use chip::movie::{Recorder, Player};
+use chip::watch::Action;
+use term_oss::{Peripheral, Palette, KeyMap, KeyConfig, AudioBackend, RenderMode, ColorDepth, Command};
+use term_oss::debugger::Command as Debug;
+use headless::Script;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+usage: chip8 run <rom> [options]
+       chip8 headless <rom> [headless options]
+       chip8 disasm <rom>
+       chip8 hash <rom>
+       chip8 asm <source> [-o <rom>]
+
+options:
+    --cycles <n>        instructions per frame (default 1000)
+    --quirks <preset>   legacy, vip, chip48, schip or xochip (default legacy,
+                        as before presets: shifts of VY, I unchanged by
+                        FX55/FX65, BNNN from V0, sprites wrapping around)
+    --load <addr>       where the ROM is loaded and starts (default 0x200)
+    --seed <n>          seed of the random numbers, for reproducible runs
+                        (default from the clock)
+    --palette <colors>  default, phosphor, amber, octo or contrast, or
+                        off,on or off,plane1,plane2,both, among black, red,
+                        green, yellow, blue, magenta, cyan, white and
+                        #rrggbb
+    --colors <n>        colors shown by the terminal, 8, 256 or truecolor, or
+                        auto for truecolor if COLORTERM says so, 256 if TERM
+                        does (default auto); the nearest are shown
+    --keys <keys>       comma separated key mapping, in order: qwerty, azerty,
+                        dvorak, numpad or 16 characters typed for keys 0 ~ F
+                        replace the whole mapping, arrows binds the arrow
+                        keys to 2, 4, 6 and 8, and <key>=<hex> binds a
+                        character or up, down, left, right, enter, tab,
+                        space, comma, insert, delete, home, end, pageup or
+                        pagedown, taking it from the debugger (default
+                        qwerty, that is x123qweasdzcvfr4)
+    --keys-file <file>  lines of <rom> <keys> applied in order before --keys,
+                        for every ROM if <rom> is *, else for the ROM with
+                        the hash printed by chip8 hash; # starts a comment
+                        (default ~/.config/chip8/keys if it exists)
+    --release <ms>      how long a key stays held after the terminal last
+                        repeated it, unless the terminal reports releases
+                        with the kitty keyboard protocol (default 150)
+    --audio <backend>   oss or none (default oss)
+    --render <mode>     how pixels are drawn in terminal cells: wide (two
+                        cells a pixel), half (half blocks, 1x2 pixels a
+                        cell), quadrant (2x2), braille (2x4) or auto for
+                        the biggest pixels fitting the terminal (default
+                        auto); a cell shows two colors at most
+    --rewind <frames>   frames kept to rewind, 0 to disable (default 600)
+    --record <movie>    record the input of every frame into a movie file
+    --play <movie>      play a movie back, with its seed, quirks, load
+                        address and cycles; the debugger, rewind and save
+                        states are off while recording or playing
+    --watch <watch>     pause after an access to <addr>[-<end>][:rwx][:log],
+                        or only log it; any access unless r, w or x is given
+
+headless options, without a terminal or sound device:
+    --cycles, --quirks, --load and --seed (default 0) as above
+    --frames <n>        quit after n frames (default 600)
+    --input <script>    keys held from given frames on, as <frame>:<keys>
+                        separated by commas, e.g. 60:5,90:,120:4a
+    --dump-screen <pbm> write the final screen as a plain PBM image
+
+save states, kept next to the ROM with the extensions .st1 ~ .st9:
+    F1 next slot   F2 save   F3 load   hold Backspace to rewind
+
+other keys:
+    F11 next palette   Esc quit
+
+debugger keys:
+    F5 pause/continue   F6 step   F12 step back   F7 step over   F8 step out
+    F9 breakpoint at cursor   F4 run to cursor   F10 show while running
+    Up/Down cursor   PageUp/PageDown memory   Home follow PC and I";
 
 fn main() {
-    let mut chip = Chip::default();
-    let mut pe = Peripheral::default();
-
-    // AUDIO TEST
-    /*
-    // sync
-    chip.load(0x800, &[
-              0xFE, 0x07,       // 800: ldd RE
-              0x3E, 0x00,       //      seq RE, 00
-              0x18, 0x00,       //      jmp 800
-              0x00, 0xEE,       //      ret
-    ]);
-    // main
-    chip.load(0x200, &[
-              0x60, 0x05,       // 200: mov R0, 05
-              0xF0, 0x18,       //      sts R0
-              0xF0, 0x15,       //      std R0
-              0x28, 0x00,       //      cal 800    // sync
-              0xF0, 0x15,       //      std R0
-              0x28, 0x00,       //      cal 800    // sync
-              0x12, 0x00,       //      jmp 200
-    ]);
-    */
-
-    // F8Z
-    chip.load(0x200, &[
-              0x14, 0x14, 0x07, 0x19, 0x61, 0x81, 0x87, 0x99, 0x81, 0x81,
-              0x87, 0x9E, 0x98, 0x90, 0x90, 0xF0, 0xE0, 0x0E, 0x33, 0x41,
-              0x89, 0x99, 0x93, 0x81, 0xC1, 0x89, 0x99, 0x91, 0x83, 0xCE,
-              0x78, 0x60, 0x07, 0x19, 0x61, 0x81, 0x91, 0xE1, 0x43, 0x86,
-              0x89, 0x81, 0x81, 0x87, 0x9E, 0xF8, 0x60, 0x07, 0x0F, 0x7F,
-              0xFF, 0xC0, 0xE0, 0xFE, 0xFF, 0x30, 0x7E, 0xFF, 0x06, 0x1B,
-              0x65, 0x8F, 0xD5, 0xBD, 0xFB, 0x7E, 0x83, 0xBB, 0xBB, 0xBB,
-              0x83, 0x7F, 0xFD, 0x00, 0x78, 0xD6, 0xFE, 0xFD, 0xBF, 0xFF,
-              0xFE, 0xFF, 0x83, 0xAB, 0xAB, 0xBB, 0x82, 0xFF, 0xBF, 0x00,
-              0x60, 0xB8, 0xFE, 0xFB, 0x7B, 0x7F, 0xFF, 0xDF, 0x83, 0xBA,
-              0xA3, 0xBB, 0x83, 0x7F, 0xFD, 0x00, 0x16, 0xD9, 0xA6, 0xBB,
-              0xFB, 0xFF, 0xDF, 0xFE, 0x82, 0xBB, 0xAB, 0xAB, 0x83, 0x7F,
-              0xF9, 0x00, 0xFE, 0xFE, 0xFE, 0xE0, 0xEF, 0xEF, 0xEF, 0x00,
-              0xEF, 0xEF, 0xEF, 0xE0, 0xFE, 0xFE, 0xFE, 0x00, 0xEF, 0xEF,
-              0xEF, 0xE0, 0xEE, 0xEE, 0xEE, 0x00, 0xFE, 0xFE, 0xFE, 0xE0,
-              0xEE, 0xEE, 0xEE, 0x00, 0x00, 0x00, 0x60, 0xE0, 0x7E, 0xFF,
-              0xBD, 0xF7, 0x7E, 0x18, 0x3C, 0x5E, 0x3C, 0x3C, 0x24, 0x00,
-              0x00, 0x60, 0x60, 0xFE, 0xFF, 0xBD, 0xF7, 0x7E, 0x18, 0x3C,
-              0x5E, 0x3C, 0x3C, 0x48, 0x00, 0x00, 0x00, 0x60, 0x60, 0xFE,
-              0xFF, 0xBD, 0xF7, 0x7E, 0x18, 0x7E, 0x1C, 0x3C, 0x3C, 0x24,
-              0x00, 0x00, 0x00, 0x00, 0x60, 0xFE, 0xFF, 0xFF, 0xBD, 0x76,
-              0x18, 0x3C, 0x5E, 0x3C, 0x3C, 0x12, 0x00, 0x00, 0x00, 0x00,
-              0x06, 0x07, 0x7E, 0xFF, 0xBD, 0xEF, 0x7E, 0x18, 0x3C, 0x7A,
-              0x3C, 0x3C, 0x24, 0x00, 0x00, 0x06, 0x06, 0x7F, 0xFF, 0xBD,
-              0xEF, 0x7E, 0x18, 0x3C, 0x7A, 0x3C, 0x3C, 0x12, 0x00, 0x00,
-              0x00, 0x06, 0x06, 0x7F, 0xFF, 0xBD, 0xEF, 0x7E, 0x18, 0x7E,
-              0x38, 0x3C, 0x3C, 0x24, 0x00, 0x00, 0x00, 0x00, 0x06, 0x7F,
-              0xFF, 0xFF, 0xBD, 0x6E, 0x18, 0x3C, 0x7A, 0x3C, 0x3C, 0x48,
-              0x00, 0x00, 0x2E, 0x0A, 0x10, 0x2E, 0xFE, 0xFF, 0xFE, 0xFF,
-              0x02, 0x01, 0x02, 0x01, 0x62, 0x04, 0xF2, 0x15, 0xF2, 0x07,
-              0x32, 0x00, 0x13, 0x2A, 0x00, 0xEE, 0x60, 0x02, 0x81, 0xD0,
-              0x71, 0x08, 0x62, 0x01, 0xA2, 0x9A, 0xFC, 0x1E, 0xF8, 0x1E,
-              0xDA, 0xBF, 0xA2, 0x2F, 0xDD, 0x04, 0xA2, 0x33, 0xD1, 0x04,
-              0xA2, 0x37, 0xDE, 0x23, 0xA3, 0x1A, 0xF5, 0x1E, 0xF0, 0x65,
-              0x61, 0x09, 0xA2, 0x3A, 0x82, 0x5E, 0x82, 0x2E, 0x82, 0x2E,
-              0x82, 0x2E, 0xF2, 0x1E, 0xD0, 0x1F, 0x00, 0xEE, 0x60, 0x00,
-              0x61, 0x19, 0xA2, 0x7A, 0xC2, 0x18, 0xF2, 0x1E, 0xD0, 0x17,
-              0x70, 0x08, 0x30, 0x40, 0x13, 0x6A, 0x00, 0xEE, 0x61, 0x10,
-              0xF1, 0x18, 0x61, 0x03, 0x85, 0x12, 0xA2, 0x9A, 0xF1, 0x1E,
-              0x61, 0x01, 0xD0, 0x1F, 0x61, 0x10, 0xD0, 0x1F, 0x23, 0x26,
-              0x61, 0x01, 0xD0, 0x1F, 0x61, 0x10, 0xD0, 0x1F, 0x82, 0x00,
-              0x80, 0x34, 0x61, 0x3F, 0x82, 0x00, 0x82, 0x12, 0x32, 0x00,
-              0x13, 0x84, 0x00, 0xE0, 0x23, 0x66, 0x00, 0xEE, 0x80, 0xA0,
-              0x8A, 0x70, 0x67, 0x40, 0x87, 0x05, 0x75, 0xFF, 0x60, 0x40,
-              0x63, 0xF0, 0x13, 0x7A, 0x80, 0xA0, 0x8A, 0x70, 0x67, 0x40,
-              0x87, 0x04, 0x75, 0x01, 0x60, 0x00, 0x63, 0x10, 0x13, 0x7A,
-              0x79, 0xFF, 0x7C, 0x10, 0x60, 0x30, 0x8C, 0x02, 0x48, 0x00,
-              0x7A, 0x01, 0x38, 0x00, 0x7A, 0xFF, 0x00, 0xEE, 0x68, 0x40,
-              0x00, 0xEE, 0x48, 0x00, 0x13, 0xDE, 0x69, 0x04, 0x00, 0xEE,
-              0x68, 0x00, 0x00, 0xEE, 0x48, 0x40, 0x13, 0xEA, 0x69, 0x04,
-              0x00, 0xEE, 0x39, 0x00, 0x13, 0xCC, 0x60, 0x07, 0xE0, 0xA1,
-              0x13, 0xE2, 0x60, 0x09, 0xE0, 0xA1, 0x13, 0xEE, 0x60, 0x04,
-              0xE0, 0xA1, 0x13, 0xAC, 0x60, 0x06, 0xE0, 0xA1, 0x13, 0xBC,
-              0x00, 0xEE, 0x60, 0x0F, 0x61, 0x0D, 0xA2, 0x02, 0xD0, 0x1F,
-              0x70, 0x0C, 0x71, 0xFB, 0xA2, 0x11, 0xD0, 0x1F, 0x70, 0x0C,
-              0x71, 0xFB, 0xA2, 0x20, 0xD0, 0x1F, 0x60, 0x10, 0x23, 0x26,
-              0x70, 0xFF, 0x30, 0x00, 0x14, 0x2E, 0x00, 0xE0, 0x65, 0x00,
-              0x66, 0x00, 0x67, 0x00, 0x68, 0x00, 0x69, 0x00, 0x6A, 0x0A,
-              0x6B, 0x0A, 0x6C, 0x00, 0x6D, 0x0A, 0x6E, 0x28, 0x23, 0x66,
-              0x23, 0x32, 0x23, 0x32, 0x23, 0xF6, 0xA3, 0x1E, 0xF5, 0x1E,
-              0xF5, 0x1E, 0xF1, 0x65, 0x8D, 0x04, 0x8E, 0x14, 0x23, 0x32,
-              0x23, 0x26, 0x14, 0x50,
-    ]);
-
-    while !chip.frame(1000, &mut pe) {
-    }
-}
-
-mod dummy {
-    extern crate chip;
-
-    #[derive(Default)]
-    pub struct Peripheral;
-
-    impl chip::Timer for Peripheral {
-        fn pump(&mut self) -> bool {
-            false
-        }
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("run") => run(&args[1..]),
+        Some("headless") => run_headless(&args[1..]),
+        Some("disasm") if args.len() == 2 => disasm(&args[1]),
+        Some("hash") if args.len() == 2 => hash(&args[1]),
+        Some("asm") if args.len() == 2 => asm(&args[1], &Path::new(&args[1]).with_extension("ch8")),
+        Some("asm") if args.len() == 4 && args[2] == "-o" => asm(&args[1], Path::new(&args[3])),
+        _ => usage(),
     }
+}
 
-    impl chip::Video for Peripheral {
-        fn clear(&mut self) {
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Report an error and exit.
+fn die<T: fmt::Display>(msg: T) -> ! {
+    eprintln!("chip8: {}", msg);
+    process::exit(1);
+}
+
+fn read_rom(path: &str) -> io::Result<Vec<u8>> {
+    let mut rom = vec![];
+    File::open(path)?.read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+/// Print a labelled listing of the ROM at `path`.
+fn disasm(path: &str) {
+    let rom = read_rom(path).unwrap_or_else(|e| die(format!("{}: {}", path, e)));
+    let room = chip::RAM_SIZE - 0x200;
+    if rom.len() > room {
+        die(format!("{}: {} bytes do not fit into RAM at 0x200, {} bytes available", path, rom.len(), room));
+    }
+    print!("{}", chip::disassemble(&rom, 0x200));
+}
+
+/// Print the hash the ROM at `path` is told by, e.g. in key mapping files.
+fn hash(path: &str) {
+    let rom = read_rom(path).unwrap_or_else(|e| die(format!("{}: {}", path, e)));
+    println!("{:016x}", chip::rom_hash(&rom));
+}
+
+/// Assemble the Octo source at `path` into a ROM at `out`.
+fn asm(path: &str, out: &Path) {
+    let mut src = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+        die(format!("{}: {}", path, e));
+    }
+    let rom = chip::assemble(&src).unwrap_or_else(|e| die(format!("{}:{}", path, e)));
+    if let Err(e) = File::create(out).and_then(|mut f| f.write_all(&rom)) {
+        die(format!("{}: {}", out.display(), e));
+    }
+}
+
+struct RunOptions {
+    rom: String,
+    cycles: usize,
+    quirks: Quirks,
+    load: u16,
+    seed: u64,
+    rewind: usize,
+    record: Option<String>,
+    play: Option<String>,
+    watchpoints: Vec<Watchpoint>,
+    term: term_oss::Options,
+    /// `--keys`, applied in order after the key mapping file.
+    keys: Vec<String>,
+    keys_file: Option<String>,
+    frames: u64,
+    input: Script,
+    dump_screen: Option<String>,
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+fn number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parse `<addr>[-<end>][:rwx][:log]`.
+fn watchpoint(spec: &str) -> Option<Watchpoint> {
+    let mut parts = spec.split(':');
+    let mut range = parts.next()?.splitn(2, '-').map(number);
+    let start = range.next()??;
+    let end = range.next().unwrap_or(Some(start))?;
+    if start > end || end >= chip::RAM_SIZE { return None }
+    let mut w = Watchpoint::new(start as u16, end as u16);
+    for part in parts {
+        match part {
+            "log" => w.action = Action::Log,
+            _ if !part.is_empty() && part.chars().all(|c| "rwx".contains(c)) => {
+                w.read = part.contains('r');
+                w.write = part.contains('w');
+                w.execute = part.contains('x');
+            },
+            _ => return None,
         }
+    }
+    Some(w)
+}
 
-        fn draw(&mut self, x: isize, y: isize, sprite: &[u8]) -> bool {
-            false
+/// Parse the options of `run`, or of `headless` if `headless`.
+fn parse_run(args: &[String], headless: bool) -> Result<RunOptions, String> {
+    let mut rom = None;
+    let mut opts = RunOptions {
+        rom: String::new(),
+        cycles: 1000,
+        quirks: Quirks::default(),
+        load: 0x200,
+        seed: if headless { 0 } else {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+        },
+        rewind: 600,
+        record: None,
+        play: None,
+        watchpoints: vec![],
+        term: Default::default(),
+        keys: vec![],
+        keys_file: None,
+        frames: 600,
+        input: Script::default(),
+        dump_screen: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.is_some() { return Err(format!("unexpected argument {}", arg)) }
+            rom = Some(arg.clone());
+            continue;
         }
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        let invalid = || format!("invalid {} {}", &arg[2..], value);
+        match arg.as_str() {
+            "--cycles" => opts.cycles = number(value).filter(|&n| n > 0).ok_or_else(invalid)?,
+            "--quirks" => opts.quirks = Quirks::preset(value).ok_or_else(invalid)?,
+            "--load" => {
+                opts.load = number(value).filter(|&a| a < chip::RAM_SIZE).ok_or_else(invalid)? as u16;
+            },
+            "--seed" => opts.seed = number(value).ok_or_else(invalid)? as u64,
+            "--rewind" if !headless => opts.rewind = number(value).ok_or_else(invalid)?,
+            "--record" if !headless => opts.record = Some(value.clone()),
+            "--play" if !headless => opts.play = Some(value.clone()),
+            "--watch" if !headless => opts.watchpoints.push(watchpoint(value).ok_or_else(invalid)?),
+            "--palette" if !headless => opts.term.palette = Palette::parse(value).ok_or_else(invalid)?,
+            "--colors" if !headless => opts.term.colors = ColorDepth::from_name(value).ok_or_else(invalid)?,
+            "--keys" if !headless => {
+                KeyMap::parse(value).ok_or_else(invalid)?;
+                opts.keys.push(value.clone());
+            },
+            "--keys-file" if !headless => opts.keys_file = Some(value.clone()),
+            "--release" if !headless => {
+                opts.term.release = Duration::from_millis(number(value).ok_or_else(invalid)? as u64);
+            },
+            "--audio" if !headless => opts.term.audio = AudioBackend::from_name(value).ok_or_else(invalid)?,
+            "--render" if !headless => opts.term.render = RenderMode::from_name(value).ok_or_else(invalid)?,
+            "--frames" if headless => opts.frames = number(value).ok_or_else(invalid)? as u64,
+            "--input" if headless => opts.input = Script::parse(value).ok_or_else(invalid)?,
+            "--dump-screen" if headless => opts.dump_screen = Some(value.clone()),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    opts.rom = rom.ok_or("missing ROM")?;
+    if opts.record.is_some() && opts.play.is_some() {
+        return Err("--record and --play are exclusive".to_string());
+    }
+    Ok(opts)
+}
 
-        fn present(&self) {
+/// Run the ROM in the terminal.
+fn run(args: &[String]) {
+    let mut opts = parse_run(args, false).unwrap_or_else(|e| {
+        eprintln!("chip8: {}", e);
+        usage();
+    });
+
+    let rom = read_rom(&opts.rom).unwrap_or_else(|e| die(format!("{}: {}", opts.rom, e)));
+    let hash = chip::rom_hash(&rom);
+    let movie = opts.play.as_ref().map(|path| read_movie(path, hash));
+    if let Some(ref movie) = movie {
+        opts.quirks = movie.quirks;
+        opts.seed = movie.seed;
+        opts.load = movie.load;
+        opts.cycles = movie.cycles as usize;
+    }
+    let mut chip = boot(&opts, &rom);
+    opts.term.keys = key_map(&opts, hash);
+
+    let mut pe = Peripheral::new(&opts.term).unwrap_or_else(|e| die(format!("audio: {}", e)));
+    let (result, recorded) = match movie {
+        Some(ref movie) => (frames(&mut chip, &mut Player::new(&mut pe, movie), opts.cycles), None),
+        None if opts.record.is_some() => {
+            let mut movie = Movie::new(hash, opts.seed, opts.quirks, opts.load, opts.cycles as u32);
+            let mut rec = Recorder::new(&mut pe, &mut movie);
+            let result = frames(&mut chip, &mut rec, opts.cycles);
+            rec.finish();
+            (result, Some(movie))
+        },
+        None => (emulate(&mut chip, &mut pe, &opts, hash), None),
+    };
+    drop(pe);   // leave raw mode before reporting
+
+    if let (Some(path), Some(movie)) = (opts.record.as_ref(), recorded) {
+        if let Err(e) = fs::write(path, movie.to_bytes()) {
+            die(format!("{}: {}", path, e));
         }
     }
+    if let Err(e) = result {
+        die(e);
+    }
+}
+
+/// The key mapping for the ROM hashed to `rom`, from the key mapping file
+/// and `--keys`.
+fn key_map(opts: &RunOptions, rom: u64) -> KeyMap {
+    let mut keys = KeyMap::default();
+    let path = opts.keys_file.clone().or_else(|| {
+        let home = env::var_os("HOME")?;
+        let path = Path::new(&home).join(".config/chip8/keys");
+        if path.exists() { path.to_str().map(String::from) } else { None }
+    });
+    if let Some(path) = path {
+        let text = fs::read_to_string(&path).unwrap_or_else(|e| die(format!("{}: {}", path, e)));
+        let config = KeyConfig::parse(&text)
+            .unwrap_or_else(|line| die(format!("{}:{}: invalid key mapping", path, line)));
+        config.apply(&mut keys, rom);
+    }
+    for spec in &opts.keys {
+        keys.apply(spec);
+    }
+    keys
+}
+
+/// Run the ROM without a terminal, for `--frames` frames at most.
+fn run_headless(args: &[String]) {
+    let opts = parse_run(args, true).unwrap_or_else(|e| {
+        eprintln!("chip8: {}", e);
+        usage();
+    });
+    let rom = read_rom(&opts.rom).unwrap_or_else(|e| die(format!("{}: {}", opts.rom, e)));
+    let mut chip = boot(&opts, &rom);
+    let mut pe = headless::Peripheral::new(opts.input.clone(), Some(opts.frames));
+    let result = if opts.frames > 0 { frames(&mut chip, &mut pe, opts.cycles) } else { Ok(()) };
 
-    impl chip::Audio for Peripheral {
-        fn buzz(&mut self, on: bool) {
+    if let Some(ref path) = opts.dump_screen {
+        if let Err(e) = fs::write(path, headless::pbm(chip.screen())) {
+            die(format!("{}: {}", path, e));
         }
     }
+    if let Err(e) = result {
+        die(e);
+    }
+}
+
+/// A chip set up by `opts`, with `rom` loaded, or exit if it does not
+/// fit.
+fn boot(opts: &RunOptions, rom: &[u8]) -> Chip {
+    let room = chip::RAM_SIZE - opts.load as usize;
+    if rom.is_empty() {
+        die(format!("{}: empty ROM", opts.rom));
+    }
+    if rom.len() > room {
+        die(format!("{}: {} bytes do not fit into RAM at {:#05X}, {} bytes available",
+                    opts.rom, rom.len(), opts.load, room));
+    }
+
+    let mut chip = Chip::default();
+    chip.set_quirks(opts.quirks);
+    chip.set_rng(Box::new(XorShift::new(opts.seed)));
+    chip.load(opts.load, rom);
+    chip.set_pc(opts.load);
+    for &w in &opts.watchpoints {
+        chip.add_watchpoint(w);
+    }
+    chip
+}
+
+/// A movie to play for the ROM hashed to `rom`.
+fn read_movie(path: &str, rom: u64) -> Movie {
+    let data = fs::read(path).unwrap_or_else(|e| die(format!("{}: {}", path, e)));
+    let movie = Movie::parse(&data).unwrap_or_else(|e| die(format!("{}: {}", path, e)));
+    if movie.rom != rom {
+        die(format!("{}: movie of another ROM", path));
+    }
+    movie
+}
+
+/// Run frames until the program exits or the user quits, without the
+/// debugger.
+fn frames<P>(chip: &mut Chip, p: &mut P, cycles: usize) -> chip::Result<()>
+    where P: Timer + Video + Audio + Input + Machine
+{
+    while chip.frame(cycles, p)? == Status::Running {
+    }
+    Ok(())
+}
+
+/// Save the state of the ROM hashed to `rom` to `path`.
+fn save_state(chip: &Chip, path: &Path, rom: u64) -> io::Result<()> {
+    fs::write(path, chip.save_state(rom, &[]))
+}
+
+fn load_state(chip: &mut Chip, path: &Path, rom: u64) -> Result<(), String> {
+    let state = fs::read(path).map_err(|e| e.to_string())?;
+    restore(chip, &state, rom)
+}
+
+fn restore(chip: &mut Chip, state: &[u8], rom: u64) -> Result<(), String> {
+    chip.load_state(state, rom).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Go back to before the last instruction executed, replaying it from
+/// the snapshot of its frame. Returns `Ok(true)` if the program quit.
+fn step_back(chip: &mut Chip, pe: &mut Peripheral, dbg: &mut Debugger, rewind: &mut Rewind, rom: u64)
+    -> chip::Result<bool>
+{
+    let target = dbg.cycles().saturating_sub(1);
+    let (at, state) = match rewind.seek(target) {
+        Some(snapshot) if dbg.cycles() > 0 => snapshot,
+        _ => {
+            pe.show_message("nothing to step back to");
+            return Ok(false);
+        },
+    };
+    if let Err(e) = restore(chip, state, rom) {
+        pe.show_message(&e);
+        return Ok(false);
+    }
+    dbg.replay(chip, pe, at, target)
+}
 
-    impl chip::Input for Peripheral {
-        fn keydown(&self, which: usize) -> bool {
-            false
+fn emulate(chip: &mut Chip, pe: &mut Peripheral, opts: &RunOptions, rom: u64) -> chip::Result<()> {
+    let mut dbg = Debugger::default();
+    let mut rewind = Rewind::new(opts.rewind);
+    loop {
+        for command in pe.commands() {
+            let exited = match command {
+                Command::Debug(Debug::Pause) if dbg.is_paused() => { dbg.resume(); false },
+                Command::Debug(Debug::Pause) => { dbg.pause(); false },
+                Command::Debug(Debug::Step) => dbg.step(chip, pe)?,
+                Command::Debug(Debug::StepBack) => step_back(chip, pe, &mut dbg, &mut rewind, rom)?,
+                Command::Debug(Debug::StepOver) => dbg.step_over(chip, pe)?,
+                Command::Debug(Debug::StepOut) => dbg.step_out(chip, pe)?,
+                Command::Debug(Debug::ToggleBreakpoint(addr)) => { dbg.toggle_breakpoint(addr); false },
+                Command::Debug(Debug::RunTo(addr)) => { dbg.run_to(addr); false },
+                Command::SaveState(slot) => {
+                    let path = Path::new(&opts.rom).with_extension(format!("st{}", slot));
+                    let text = match save_state(chip, &path, rom) {
+                        Ok(()) => format!("saved slot {}", slot),
+                        Err(e) => format!("slot {}: {}", slot, e),
+                    };
+                    pe.show_message(&text);
+                    false
+                },
+                Command::LoadState(slot) => {
+                    let path = Path::new(&opts.rom).with_extension(format!("st{}", slot));
+                    let text = match load_state(chip, &path, rom) {
+                        Ok(()) => format!("loaded slot {}", slot),
+                        Err(e) => format!("slot {}: {}", slot, e),
+                    };
+                    pe.show_message(&text);
+                    false
+                },
+            };
+            if exited {
+                halt(pe, &mut dbg);
+            }
         }
 
-        fn key(&self) -> Option<u8> {
-            None
+        if pe.rewinding() {
+            // A frame back per frame, not running the program.
+            if let Some((at, state)) = rewind.pop() {
+                if let Err(e) = restore(chip, &state, rom) {
+                    pe.show_message(&e);
+                }
+                dbg.set_cycles(at);
+            }
+            pe.show_debugger(chip, &dbg);
+            pe.present(chip.screen());
+            if pe.pump() {
+                return Ok(());
+            }
+            continue;
+        }
+        if !dbg.is_paused() {
+            // Before the timers count down, as `Debugger::replay` expects.
+            rewind.push(dbg.cycles(), chip.save_state(rom, &[]));
+        }
+        pe.show_debugger(chip, &dbg);
+        match dbg.frame(chip, opts.cycles, pe)? {
+            Status::Running => (),
+            Status::Exited => halt(pe, &mut dbg),
+            Status::Quit => return Ok(()),
         }
     }
 }
 
+/// The program exited: keep showing it, paused, until the user quits.
+fn halt(pe: &mut Peripheral, dbg: &mut Debugger) {
+    dbg.pause();
+    pe.show_message("program exited");
+}
@@ -2,8 +2,11 @@
 //!
 //! By default, OSS provides `8kHz u8 mono` sound, which is enough for a
 //! buzzer. Thus no parameter tuning will be done here.
+//!
+//! XO-CHIP audio patterns are resampled to that rate by picking the
+//! nearest bit of the pattern for every sample.
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
 use std::sync::mpsc;    // Multiple Producer Single Consumer
 use std::thread;
 use std::time::Duration;
@@ -16,7 +19,7 @@ mod test {
 
     #[test]
     fn buzz() {
-        let b: Buzzer = Default::default();
+        let mut b: Buzzer = Default::default();
         b.buzz(true);
         thread::sleep(Duration::from_millis(500));
         b.buzz(false);
@@ -28,6 +31,23 @@ mod test {
             thread::sleep(Duration::from_millis(100));
         }
     }
+
+    #[test]
+    fn pattern() {
+        let mut b: Buzzer = Default::default();
+        b.pattern(&[0xF0; 16], 64);
+        b.buzz(true);
+        thread::sleep(Duration::from_millis(500));
+        b.pattern(&[0xF0; 16], 112);
+        thread::sleep(Duration::from_millis(500));
+        b.buzz(false);
+    }
+
+    #[test]
+    fn pattern_rate() {
+        assert_eq!(playback_rate(64), 4000.0);
+        assert_eq!(playback_rate(112), 8000.0);
+    }
 }
 
 type Sample = u8;
@@ -39,27 +59,72 @@ const ZEROS: [Sample; BUFFER_SIZE] = [ZERO_DC; BUFFER_SIZE];
 const HIGHS: [Sample; BUFFER_SIZE] = [ZERO_DC + AMPLITUDE; BUFFER_SIZE];
 const LOWS:  [Sample; BUFFER_SIZE] = [ZERO_DC - AMPLITUDE; BUFFER_SIZE];
 
+/// XO-CHIP pattern playback rate in samples per second.
+fn playback_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/// 128 1-bit samples, most significant bit first.
+type Pattern = [u8; 16];
+const PATTERN_BITS: f64 = 128.0;
+
 #[derive(Debug)]
 enum Message {
     On,
     Off,
+    /// Pattern and its playback rate.
+    Pattern(Pattern, f64),
     Quit,
 }
 
+/// Which way the buzzer makes sound.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Write to the OSS device `/dev/dsp`.
+    #[default]
+    Oss,
+    /// Make no sound.
+    Silent,
+}
+
+impl Backend {
+    /// Backends by name, for users to choose from.
+    pub const NAMES: &'static [(&'static str, Backend)] = &[
+        ("oss", Backend::Oss),
+        ("none", Backend::Silent),
+    ];
+
+    /// Look up a backend by its name in `NAMES`.
+    pub fn from_name(name: &str) -> Option<Backend> {
+        Backend::NAMES.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, b)| b)
+    }
+}
+
 pub struct Buzzer {
     thread: Option<thread::JoinHandle<()>>,
-    tx: mpsc::Sender<Message>,
+    /// None when silent.
+    tx: Option<mpsc::Sender<Message>>,
     on: bool,
 }
 
 impl Buzzer {
-    /// Only allow construction from Default trait.
-    fn new() -> Buzzer {
-        let mut dsp = File::create("/dev/dsp").unwrap();
+    pub fn new(backend: Backend) -> io::Result<Buzzer> {
+        match backend {
+            Backend::Oss => Buzzer::oss(),
+            Backend::Silent => Ok(Buzzer { thread: None, tx: None, on: false }),
+        }
+    }
+
+    fn oss() -> io::Result<Buzzer> {
+        let mut dsp = File::create("/dev/dsp")?;
         let (tx, rx) = mpsc::channel();
         let thread = move || {
             let mut on = false;
             let mut is_high = false;
+            let mut pattern = None;
+            let mut phase = 0.0;    // position in pattern, in bits
             'main: loop {
                 'recv: loop {
                     match rx.try_recv() {
@@ -68,49 +133,73 @@ impl Buzzer {
                         Ok(Message::Quit) => break 'main,
                         Ok(Message::On) => on = true,
                         Ok(Message::Off) => on = false,
+                        Ok(Message::Pattern(p, rate)) => pattern = Some((p, rate)),
                     }
                 }
 
                 //println!("{}", on);
-                if on {
-                    if is_high { dsp.write_all(&HIGHS).unwrap() }
-                    else { dsp.write_all(&LOWS).unwrap() }
-                    is_high = !is_high;
+                match (on, pattern) {
+                    (false, _) => dsp.write_all(&ZEROS).unwrap(),
+                    (true, None) => {
+                        if is_high { dsp.write_all(&HIGHS).unwrap() }
+                        else { dsp.write_all(&LOWS).unwrap() }
+                        is_high = !is_high;
+                    },
+                    (true, Some((p, rate))) => {
+                        let mut samples = ZEROS;
+                        for x in samples.iter_mut() {
+                            let bit = phase as usize;
+                            let high = p[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                            *x = if high { ZERO_DC + AMPLITUDE } else { ZERO_DC - AMPLITUDE };
+                            phase = (phase + rate / SAMPLE_RATE as f64) % PATTERN_BITS;
+                        }
+                        dsp.write_all(&samples).unwrap();
+                    },
                 }
-                else { dsp.write_all(&ZEROS).unwrap() }
                 dsp.flush().unwrap();
                 thread::sleep(Duration::from_millis((BUFFER_SIZE * 1000 / SAMPLE_RATE) as u64));
             }
         };
         let child = thread::Builder::new()
             .name("Buzzer".to_string())
-            .spawn(thread)
-            .unwrap();
-        Buzzer {
+            .spawn(thread)?;
+        Ok(Buzzer {
             thread: Some(child),
-            tx: tx,
+            tx: Some(tx),
             on: false,
+        })
+    }
+
+    fn send(&self, msg: Message) {
+        if let Some(ref tx) = self.tx {
+            tx.send(msg).unwrap();
         }
     }
 
     pub fn buzz(&mut self, on: bool) {
         if self.on == on { return }
         self.on = on;
-        let msg = if on { Message::On } else { Message::Off };
-        self.tx.send(msg).unwrap();
+        self.send(if on { Message::On } else { Message::Off });
+    }
+
+    /// Buzz with `pattern` played at the XO-CHIP `pitch` from now on.
+    pub fn pattern(&mut self, pattern: &Pattern, pitch: u8) {
+        self.send(Message::Pattern(*pattern, playback_rate(pitch)));
     }
 }
 
 impl Default for Buzzer {
     fn default() -> Buzzer {
-        Buzzer::new()
+        Buzzer::new(Backend::Oss).unwrap()
     }
 }
 
 impl Drop for Buzzer {
     fn drop(&mut self) {
-        self.tx.send(Message::Quit).unwrap();
-        self.thread.take().unwrap().join().unwrap();
+        self.send(Message::Quit);
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
     }
 }
 
@@ -0,0 +1,188 @@
+//! # Debugger Pane
+//!
+//! Registers, stack, latest watchpoint hits, disassembly around PC and a
+//! memory hex view, shown next to the game screen.
+//!
+//! F5 pauses or continues, F6 steps, F12 steps back, F7 steps over, F8
+//! steps out, F9 toggles a breakpoint at the cursor, F4 runs to the
+//! cursor and F10 keeps the pane shown while running. Up and Down move the cursor,
+//! PageUp and PageDown scroll the memory view, Home makes both follow
+//! PC and I again.
+
+use chip::{Chip, Debugger, Instruction};
+use chip::instruction;
+use chip::watch::Access;
+use rustbox::keyboard::Key;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let mut pane = Pane::default();
+        let mut commands = vec![];
+        pane.pc = 0x210;
+        assert!(pane.handle_key(Key::Up, &mut commands));
+        assert!(pane.handle_key(Key::F(9), &mut commands));
+        assert!(pane.handle_key(Key::F(4), &mut commands));
+        assert!(!pane.handle_key(Key::Char('x'), &mut commands));
+        assert_eq!(commands, vec![Command::ToggleBreakpoint(0x20E), Command::RunTo(0x20E)]);
+        assert_eq!(pane.cursor, None, "running again follows PC");
+    }
+
+    #[test]
+    fn lines() {
+        let mut chip = Chip::default();
+        chip.load(0x200, &[0x60, 0x05, 0xF0, 0x00, 0x12, 0x34]);
+        let mut dbg = Debugger::default();
+        dbg.toggle_breakpoint(0x202);
+        let lines = Pane::default().lines(&chip, &dbg);
+        let text: Vec<&str> = lines.iter().map(|l| l.0.as_str()).collect();
+        assert!(text.contains(&" > 0200  6005  v0 := 0x05"), "{:#?}", text);
+        assert!(text.contains(&"*  0202  F000  i := long 0x1234"), "{:#?}", text);
+        assert!(lines.iter().any(|&(ref t, cursor)| cursor && t.contains("0200")));
+    }
+}
+
+/// Columns taken by the pane.
+pub const WIDTH: usize = 40;
+
+/// Instructions shown before and after the cursor.
+const CONTEXT: u16 = 5;
+/// Rows of the memory view, of 8 bytes each.
+const MEMORY_ROWS: u16 = 8;
+/// Latest watchpoint hits shown.
+const HITS: usize = 3;
+
+/// What the user asked the debugger to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Pause, or continue if paused.
+    Pause,
+    Step,
+    /// Back to before the last instruction executed.
+    StepBack,
+    StepOver,
+    StepOut,
+    ToggleBreakpoint(u16),
+    RunTo(u16),
+}
+
+#[derive(Default)]
+pub struct Pane {
+    /// Shown while running too, not only when paused.
+    pub visible: bool,
+    /// Disassembly cursor, following PC when None.
+    cursor: Option<u16>,
+    /// First address of the memory view, following I when None.
+    memory: Option<u16>,
+    /// PC and I when last shown.
+    pc: u16,
+    reg_i: u16,
+}
+
+impl Pane {
+    fn cursor(&self) -> u16 {
+        self.cursor.unwrap_or(self.pc)
+    }
+
+    fn memory(&self) -> u16 {
+        self.memory.unwrap_or(self.reg_i & !7)
+    }
+
+    /// Returns false if `key` is not for the debugger.
+    pub fn handle_key(&mut self, key: Key, commands: &mut Vec<Command>) -> bool {
+        let command = match key {
+            Key::F(4) => Some(Command::RunTo(self.cursor())),
+            Key::F(5) => Some(Command::Pause),
+            Key::F(6) => Some(Command::Step),
+            Key::F(7) => Some(Command::StepOver),
+            Key::F(8) => Some(Command::StepOut),
+            Key::F(9) => Some(Command::ToggleBreakpoint(self.cursor())),
+            Key::F(12) => Some(Command::StepBack),
+            Key::F(10) => { self.visible = !self.visible; None },
+            Key::Up => { self.cursor = Some(self.cursor().wrapping_sub(2)); None },
+            Key::Down => { self.cursor = Some(self.cursor().wrapping_add(2)); None },
+            Key::PageUp => { self.memory = Some(self.memory().wrapping_sub(8 * MEMORY_ROWS)); None },
+            Key::PageDown => { self.memory = Some(self.memory().wrapping_add(8 * MEMORY_ROWS)); None },
+            Key::Home => { self.cursor = None; self.memory = None; None },
+            _ => return false,
+        };
+        if let Some(command) = command {
+            // Follow PC again once running.
+            if !matches!(command, Command::ToggleBreakpoint(_)) {
+                self.cursor = None;
+            }
+            commands.push(command);
+        }
+        true
+    }
+
+    /// Text of the pane, with whether each line is under the cursor.
+    pub fn lines(&mut self, chip: &Chip, dbg: &Debugger) -> Vec<(String, bool)> {
+        self.pc = chip.pc();
+        self.reg_i = chip.reg_i();
+        let (delay, sound) = chip.timers();
+        let regs = chip.regs();
+
+        let mut lines = vec![];
+        let mut line = |text: String| lines.push((text, false));
+        line(format!("PC {:04X}  I {:04X}  DT {:02X}  ST {:02X}  {}",
+                     self.pc, self.reg_i, delay, sound,
+                     if dbg.is_paused() { "PAUSED" } else if chip.waiting_for_key() { "KEY" } else { "" }));
+        for (i, row) in regs.chunks(4).enumerate() {
+            line(row.iter().enumerate()
+                 .map(|(j, v)| format!("V{:X} {:02X}", i * 4 + j, v))
+                 .collect::<Vec<_>>()
+                 .join("  "));
+        }
+        line(format!("Stack {}", chip.stack().iter()
+                     .map(|a| format!("{:04X}", a))
+                     .collect::<Vec<_>>()
+                     .join(" ")));
+        let hits: Vec<_> = dbg.hits().collect();
+        for hit in &hits[hits.len().saturating_sub(HITS)..] {
+            let access = match hit.access {
+                Access::Read => "read",
+                Access::Write => "write",
+                Access::Execute => "exec",
+            };
+            line(format!("{:04X} {} {:04X} = {:02X}", hit.pc, access, hit.addr, hit.value));
+        }
+        line(String::new());
+
+        let cursor = self.cursor();
+        for k in 0..2 * CONTEXT + 1 {
+            let addr = cursor.wrapping_sub(2 * CONTEXT).wrapping_add(2 * k);
+            let text = format!("{}{} {:04X}  {}",
+                               if dbg.is_breakpoint(addr) { '*' } else { ' ' },
+                               if addr == self.pc { '>' } else { ' ' },
+                               addr, disassemble(chip, addr));
+            lines.push((text, addr == cursor));
+        }
+        lines.push((String::new(), false));
+
+        let memory = self.memory();
+        for row in 0..MEMORY_ROWS {
+            let addr = memory.wrapping_add(8 * row);
+            let bytes = chip.memory(addr, 8).iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            lines.push((format!("{:04X}  {}", addr, bytes), false));
+        }
+        lines
+    }
+}
+
+/// The word at `addr` and its mnemonic.
+fn disassemble(chip: &Chip, addr: u16) -> String {
+    let word = |addr| chip.memory(addr, 2).iter().fold(0, |w, &b| w << 8 | b as u16);
+    let inst = word(addr);
+    match instruction::decode(inst) {
+        Some(Instruction::LoadLongI) => format!("{:04X}  i := long 0x{:04X}", inst, word(addr.wrapping_add(2))),
+        Some(i) => format!("{:04X}  {}", inst, i),
+        None => format!("{:04X}", inst),
+    }
+}
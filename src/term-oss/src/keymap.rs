@@ -0,0 +1,227 @@
+//! Keys of the terminal standing for the hex keypad, from layouts and
+//! single bindings, for every ROM or for ROMs by their hash.
+
+use rustbox::keyboard::Key;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default() {
+        let k = KeyMap::default();
+        assert_eq!(k.key(Key::Char('x')), Some(0));
+        assert_eq!(k.key(Key::Char('4')), Some(0xF));
+        assert_eq!(k.key(Key::Char('v')), Some(0xC));
+        assert_eq!(k.key(Key::Char('p')), None);
+        assert_eq!(k.key(Key::Up), None);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(KeyMap::parse("0123456789abcdef").unwrap().key(Key::Char('b')), Some(0xB));
+        assert!(KeyMap::parse("0123456789abcde").is_none());
+        assert!(KeyMap::parse("0123456789abcdee").is_none());
+
+        let k = KeyMap::parse("azerty,arrows,space=5,x=").unwrap();
+        assert_eq!(k.key(Key::Char('a')), Some(4));
+        assert_eq!(k.key(Key::Char('q')), Some(7), "layouts replace the bindings before");
+        assert_eq!(k.key(Key::Up), Some(2));
+        assert_eq!(k.key(Key::Char(' ')), Some(5));
+        assert_eq!(k.key(Key::Char('x')), None);
+
+        let k = KeyMap::parse("numpad,enter=0,comma=a,==b").unwrap();
+        assert_eq!(k.key(Key::Char('7')), Some(7));
+        assert_eq!(k.key(Key::Char('/')), Some(0xA));
+        assert_eq!(k.key(Key::Enter), Some(0), "rebound");
+        assert_eq!(k.key(Key::Char(',')), Some(0xA));
+        assert_eq!(k.key(Key::Char('=')), Some(0xB));
+
+        assert!(KeyMap::parse("colemak").is_none());
+        assert!(KeyMap::parse("f1=5").is_none());
+        assert!(KeyMap::parse("up=g").is_none());
+    }
+
+    #[test]
+    fn config() {
+        let config = KeyConfig::parse("# team defaults\n* dvorak\n\n00000000deadbeef qwerty,arrows\n").unwrap();
+        let mut k = KeyMap::default();
+        config.apply(&mut k, 0xdead_beef);
+        assert_eq!(k.key(Key::Char('q')), Some(4));
+        assert_eq!(k.key(Key::Left), Some(4));
+
+        let mut k = KeyMap::default();
+        config.apply(&mut k, 1);
+        assert_eq!(k.key(Key::Char('q')), Some(0));
+        assert_eq!(k.key(Key::Left), None);
+
+        assert_eq!(KeyConfig::parse("* qwerty\n*\n").unwrap_err(), 2);
+        assert_eq!(KeyConfig::parse("beef colemak").unwrap_err(), 1);
+        assert_eq!(KeyConfig::parse("x qwerty").unwrap_err(), 1);
+    }
+}
+
+//                       0123456789ABCDEF
+pub const QWERTY: &str = "x123qweasdzcvfr4";
+// QWERTY KEYBOARD               HEX KEYBOARD
+//     1 2 3 4                     1 2 3 F
+//     q w e r                     4 5 6 E
+//     a s d f                     7 8 9 D
+//     z x c v                     A 0 B C
+/// The same keys typed on the other layouts.
+pub const AZERTY: &str = "x&é\"azeqsdwcvfr'";
+pub const DVORAK: &str = "q123',.aoe;jkup4";
+/// The digits for themselves, the operators and Enter for `A` ~ `F`.
+pub const NUMPAD: &[Key] = &[
+    Key::Char('0'), Key::Char('1'), Key::Char('2'), Key::Char('3'),
+    Key::Char('4'), Key::Char('5'), Key::Char('6'), Key::Char('7'),
+    Key::Char('8'), Key::Char('9'), Key::Char('/'), Key::Char('*'),
+    Key::Char('-'), Key::Char('+'), Key::Enter, Key::Char('.'),
+];
+/// The arrow keys for the directions of most games.
+pub const ARROWS: &[(Key, usize)] = &[(Key::Up, 2), (Key::Left, 4), (Key::Right, 6), (Key::Down, 8)];
+
+/// Keys but characters, named in bindings. The others are hotkeys.
+const NAMES: &[(&str, Key)] = &[
+    ("up", Key::Up),
+    ("down", Key::Down),
+    ("left", Key::Left),
+    ("right", Key::Right),
+    ("enter", Key::Enter),
+    ("tab", Key::Tab),
+    ("space", Key::Char(' ')),
+    ("comma", Key::Char(',')),
+    ("insert", Key::Insert),
+    ("delete", Key::Delete),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("pageup", Key::PageUp),
+    ("pagedown", Key::PageDown),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    /// Each key of the terminal bound, with its key `0` ~ `F`.
+    bindings: Vec<(Key, usize)>,
+}
+
+impl KeyMap {
+    /// Parse a mapping on top of the default one, see `apply`.
+    pub fn parse(spec: &str) -> Option<KeyMap> {
+        let mut keys = KeyMap::default();
+        keys.apply(spec)?;
+        Some(keys)
+    }
+
+    /// Apply comma separated items in order:
+    /// - a layout replacing all bindings: `qwerty`, `azerty`, `dvorak`,
+    ///   `numpad`, or the 16 distinct characters typed for keys `0` ~ `F`,
+    /// - `arrows`, binding the arrow keys to `2`, `4`, `6` and `8`,
+    /// - `<key>=<hex>`, binding a character or named key to a key,
+    ///   or unbinding it if the key is left out.
+    ///
+    /// Stops at the first invalid item.
+    pub fn apply(&mut self, spec: &str) -> Option<()> {
+        for item in spec.split(',') {
+            match item {
+                "qwerty" => *self = KeyMap::layout(QWERTY)?,
+                "azerty" => *self = KeyMap::layout(AZERTY)?,
+                "dvorak" => *self = KeyMap::layout(DVORAK)?,
+                "numpad" => self.bindings = NUMPAD.iter().cloned().zip(0..).collect(),
+                "arrows" => {
+                    for &(key, k) in ARROWS {
+                        self.bind(key, Some(k));
+                    }
+                },
+                _ if item.chars().count() == 16 => *self = KeyMap::layout(item)?,
+                _ => {
+                    let (name, hex) = item.rsplit_once('=')?;
+                    let key = match NAMES.iter().find(|&&(n, _)| n == name) {
+                        Some(&(_, key)) => key,
+                        None => {
+                            let mut chars = name.chars();
+                            match (chars.next(), chars.next()) {
+                                (Some(ch), None) => Key::Char(ch),
+                                _ => return None,
+                            }
+                        },
+                    };
+                    let k = match hex {
+                        "" => None,
+                        _ => Some(u8::from_str_radix(hex, 16).ok().filter(|&k| k < 16)? as usize),
+                    };
+                    self.bind(key, k);
+                },
+            }
+        }
+        Some(())
+    }
+
+    /// The 16 distinct characters typed for keys `0` ~ `F`.
+    fn layout(chars: &str) -> Option<KeyMap> {
+        let mut bindings: Vec<(Key, usize)> = vec![];
+        for (ch, k) in chars.chars().zip(0..) {
+            if k == 16 || bindings.iter().any(|&(key, _)| key == Key::Char(ch)) { return None }
+            bindings.push((Key::Char(ch), k));
+        }
+        if bindings.len() == 16 { Some(KeyMap { bindings }) } else { None }
+    }
+
+    /// Bind `key` to `k`, or unbind it.
+    fn bind(&mut self, key: Key, k: Option<usize>) {
+        self.bindings.retain(|&(other, _)| other != key);
+        if let Some(k) = k {
+            self.bindings.push((key, k));
+        }
+    }
+
+    /// The key typed with `key`.
+    pub fn key(&self, key: Key) -> Option<usize> {
+        self.bindings.iter().find(|&&(other, _)| other == key).map(|&(_, k)| k)
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::layout(QWERTY).unwrap()
+    }
+}
+
+/// A key mapping file: lines of `<rom> <keys>`, the ROM being `*` for
+/// every ROM or the hash of one in hex, and the keys as for
+/// `KeyMap::apply`. Empty lines and lines starting with `#` are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyConfig {
+    /// The ROM hash, if not for every ROM, and the keys, in order.
+    lines: Vec<(Option<u64>, String)>,
+}
+
+impl KeyConfig {
+    /// Fails with the number of the first invalid line, from 1.
+    pub fn parse(text: &str) -> Result<KeyConfig, usize> {
+        let mut config = KeyConfig::default();
+        for (n, line) in (1usize..).zip(text.lines()) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+            let (rom, spec) = line.split_once(char::is_whitespace).ok_or(n)?;
+            let rom = match rom {
+                "*" => None,
+                hash => Some(u64::from_str_radix(hash, 16).map_err(|_| n)?),
+            };
+            let spec = spec.trim();
+            KeyMap::parse(spec).ok_or(n)?;
+            config.lines.push((rom, spec.to_string()));
+        }
+        Ok(config)
+    }
+
+    /// Apply the lines for every ROM and for the ROM hashed to `rom`,
+    /// in order.
+    pub fn apply(&self, keys: &mut KeyMap, rom: u64) {
+        for &(hash, ref spec) in &self.lines {
+            if hash.is_none_or(|hash| hash == rom) {
+                keys.apply(spec);
+            }
+        }
+    }
+}
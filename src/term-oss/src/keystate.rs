@@ -0,0 +1,96 @@
+//! Which keys of the hex keypad are held. Most terminals only send a key
+//! when it is typed, again and again while it is held, so a key is held
+//! until the release timeout goes by without it being sent again. Those
+//! reporting releases tell when it is.
+
+use std::time::Duration;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn repeats() {
+        let mut k = KeyState::default();
+        k.set_release(Duration::from_millis(100));
+        k.press(5, 0);
+        assert!(k.frame(16 * MS)[5]);
+        // Repeated before the timeout: still held.
+        k.press(5, 90 * MS);
+        assert!(k.frame(150 * MS)[5]);
+        assert!(!k.frame(200 * MS)[5]);
+    }
+
+    #[test]
+    fn releases() {
+        let mut k = KeyState::default();
+        k.report_releases();
+        k.press(1, 0);
+        assert!(k.frame(10_000 * MS)[1], "held until released");
+        k.release(1);
+        assert!(!k.frame(10_016 * MS)[1]);
+
+        // Pressed and released within a frame: held for it.
+        k.press(2, 10_020 * MS);
+        k.release(2);
+        assert!(k.frame(10_032 * MS)[2]);
+        assert!(!k.frame(10_048 * MS)[2]);
+    }
+}
+
+/// How long a key stays held after it was last sent, by default. Typed
+/// keys repeat at least this often, after a first delay the held keys
+/// are let go for.
+pub const RELEASE: Duration = Duration::from_millis(150);
+
+#[derive(Debug, Clone)]
+pub struct KeyState {
+    /// For each key, held until the time in ns, `u64::MAX` until released.
+    until: [u64; 16],
+    /// Pressed since the last frame, bit N for key N, held in the frame
+    /// however briefly.
+    pressed: u16,
+    /// Release timeout in ns.
+    release: u64,
+    /// The terminal reports releases: no timeout.
+    releases: bool,
+}
+
+impl KeyState {
+    pub fn set_release(&mut self, release: Duration) {
+        self.release = release.as_nanos() as u64;
+    }
+
+    /// Once the terminal is known to report releases.
+    pub fn report_releases(&mut self) {
+        self.releases = true;
+    }
+
+    /// `key` typed or repeated at `now` in ns.
+    pub fn press(&mut self, key: usize, now: u64) {
+        self.until[key] = if self.releases { u64::MAX } else { now + self.release };
+        self.pressed |= 1 << key;
+    }
+
+    pub fn release(&mut self, key: usize) {
+        self.until[key] = 0;
+    }
+
+    /// Keys held in the frame ending at `now` in ns.
+    pub fn frame(&mut self, now: u64) -> [bool; 16] {
+        let mut keydowns = [false; 16];
+        for (key, down) in keydowns.iter_mut().enumerate() {
+            *down = now < self.until[key] || self.pressed & 1 << key != 0;
+        }
+        self.pressed = 0;
+        keydowns
+    }
+}
+
+impl Default for KeyState {
+    fn default() -> KeyState {
+        KeyState { until: [0; 16], pressed: 0, release: RELEASE.as_nanos() as u64, releases: false }
+    }
+}
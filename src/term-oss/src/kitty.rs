@@ -0,0 +1,149 @@
+//! The kitty keyboard protocol, for terminals reporting key releases:
+//! <https://sw.kovidgoyal.net/kitty/keyboard-protocol/>.
+//!
+//! Termbox knows nothing of it and hands its escape sequences over as
+//! `Esc` followed by their characters, which are parsed back here.
+//! Terminals without it ignore the request and send keys as usual.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use rustbox::keyboard::Key;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse("97u"), Some(Sequence::Key(Key::Char('a'), Action::Press)));
+        assert_eq!(parse("97;1:2u"), Some(Sequence::Key(Key::Char('a'), Action::Repeat)));
+        assert_eq!(parse("97;2:3u"), Some(Sequence::Key(Key::Char('a'), Action::Release)), "shifted");
+        assert_eq!(parse("99;5u"), Some(Sequence::Key(Key::Ctrl('c'), Action::Press)));
+        assert_eq!(parse("27u"), Some(Sequence::Key(Key::Esc, Action::Press)));
+        assert_eq!(parse("127;1:3u"), Some(Sequence::Key(Key::Backspace, Action::Release)));
+        assert_eq!(parse("A"), Some(Sequence::Key(Key::Up, Action::Press)));
+        assert_eq!(parse("1;1:3P"), Some(Sequence::Key(Key::F(1), Action::Release)));
+        assert_eq!(parse("13~"), Some(Sequence::Key(Key::F(3), Action::Press)));
+        assert_eq!(parse("24;1:2~"), Some(Sequence::Key(Key::F(12), Action::Repeat)));
+        assert_eq!(parse("6~"), Some(Sequence::Key(Key::PageDown, Action::Press)));
+        assert_eq!(parse("57407u"), Some(Sequence::Key(Key::Char('8'), Action::Press)), "keypad");
+        assert_eq!(parse("57410;1:3u"), Some(Sequence::Key(Key::Char('/'), Action::Release)));
+        assert_eq!(parse("57414u"), Some(Sequence::Key(Key::Enter, Action::Press)));
+    }
+
+    #[test]
+    fn other() {
+        assert_eq!(parse("?11u"), Some(Sequence::Flags(11)));
+        assert_eq!(parse("?1;2c"), None, "device attributes");
+        assert_eq!(parse("97;1:4u"), None);
+        assert_eq!(parse("99~"), None);
+        assert_eq!(parse(""), None);
+    }
+}
+
+/// Push the flags to disambiguate keys (1), report repeats and releases
+/// (2) and report all keys as escape sequences (8), then ask for them,
+/// which only terminals supporting the protocol answer.
+const ENABLE: &str = "\x1b[>11u\x1b[?u";
+/// Pop the flags.
+const DISABLE: &str = "\x1b[<u";
+
+/// Keypad keys from `KP_DECIMAL` on, as typed without the protocol.
+const KEYPAD: [char; 5] = ['.', '/', '*', '-', '+'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Press,
+    Repeat,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequence {
+    Key(Key, Action),
+    /// The flags asked for: the protocol is supported.
+    Flags(u32),
+}
+
+/// Ask the terminal to report key releases if it can.
+pub fn enable() {
+    write(ENABLE);
+}
+
+/// Back to the keys as before `enable`.
+pub fn disable() {
+    write(DISABLE);
+}
+
+/// To the terminal termbox uses. Nothing to do without one.
+fn write(seq: &str) {
+    if let Ok(mut tty) = OpenOptions::new().write(true).open("/dev/tty") {
+        let _ = tty.write_all(seq.as_bytes());
+    }
+}
+
+/// Parse the control sequence after `ESC [`, e.g. `97;1:3u` for `a`
+/// released. `None` for any other sequence.
+pub fn parse(seq: &str) -> Option<Sequence> {
+    let last = seq.chars().last()?;
+    let params = &seq[..seq.len() - last.len_utf8()];
+    if let Some(flags) = params.strip_prefix('?') {
+        return if last == 'u' { flags.parse().ok().map(Sequence::Flags) } else { None };
+    }
+
+    // <code>[:<alternates>];<modifiers>[:<action>], the code of the
+    // unshifted key.
+    let mut fields = params.split(';');
+    let code = match fields.next().and_then(|f| f.split(':').next()) {
+        Some("") | None => 1,
+        Some(code) => code.parse().ok()?,
+    };
+    let mut modifiers = fields.next().unwrap_or("").split(':');
+    let mods = match modifiers.next() {
+        Some("") | None => 0,
+        Some(m) => m.parse::<u32>().ok()?.saturating_sub(1),
+    };
+    let action = match modifiers.next() {
+        None | Some("1") => Action::Press,
+        Some("2") => Action::Repeat,
+        Some("3") => Action::Release,
+        Some(_) => return None,
+    };
+    let ctrl = mods & 4 != 0;
+
+    let key = match (last, code) {
+        ('u', 9) => Key::Tab,
+        ('u', 13) => Key::Enter,
+        ('u', 27) => Key::Esc,
+        ('u', 127) => Key::Backspace,
+        // The keypad, told apart from the other keys.
+        ('u', n @ 57399..=57408) => Key::Char(char::from_digit(n - 57399, 10)?),
+        ('u', n @ 57409..=57413) => Key::Char(KEYPAD[(n - 57409) as usize]),
+        ('u', 57414) => Key::Enter,
+        ('u', code) => {
+            let ch = char::from_u32(code)?;
+            if ctrl { Key::Ctrl(ch) } else { Key::Char(ch) }
+        },
+        ('~', 2) => Key::Insert,
+        ('~', 3) => Key::Delete,
+        ('~', 5) => Key::PageUp,
+        ('~', 6) => Key::PageDown,
+        ('~', 7) => Key::Home,
+        ('~', 8) => Key::End,
+        ('~', n @ 11..=15) => Key::F(n - 10),
+        ('~', n @ 17..=21) => Key::F(n - 11),
+        ('~', n @ 23..=24) => Key::F(n - 12),
+        ('A', 1) => Key::Up,
+        ('B', 1) => Key::Down,
+        ('C', 1) => Key::Right,
+        ('D', 1) => Key::Left,
+        ('H', 1) => Key::Home,
+        ('F', 1) => Key::End,
+        ('P', 1) => Key::F(1),
+        ('Q', 1) => Key::F(2),
+        ('S', 1) => Key::F(4),
+        _ => return None,
+    };
+    Some(Sequence::Key(key, action))
+}
+
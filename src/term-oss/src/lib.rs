@@ -1,22 +1,107 @@
+// Tests live at the top of each file.
+#![allow(clippy::items_after_test_module)]
+
 extern crate chip;
 extern crate time;
 extern crate rustbox;
 extern crate unicode_width;
+extern crate termbox_sys;
 
 mod ui;
 mod audio;
-mod modulo;
+mod keystate;
+mod kitty;
+mod render;
+mod truecolor;
+pub mod keymap;
+pub mod palette;
+pub mod debugger;
 use ui::Terminal;
 use audio::Buzzer;
-use chip::{Timer, Video, Audio, Input};
+pub use audio::Backend as AudioBackend;
+pub use keymap::{KeyMap, KeyConfig};
+pub use palette::Palette;
+pub use palette::Depth as ColorDepth;
+pub use render::Mode as RenderMode;
+use chip::{Chip, Debugger, Framebuffer, Timer, Video, Audio, Input, Machine};
+use std::io;
 use std::time::Duration;
 
+/// What the user asked for, besides playing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Debug(debugger::Command),
+    /// F2, save the state to the numbered slot, selected with F1.
+    SaveState(u8),
+    /// F3, load the state from the numbered slot.
+    LoadState(u8),
+}
+
+/// How the terminal looks, reads keys and plays sound.
+pub struct Options {
+    pub palette: Palette,
+    pub colors: ColorDepth,
+    pub keys: KeyMap,
+    /// How long a key stays held after the terminal last sent it, for
+    /// terminals not reporting key releases.
+    pub release: Duration,
+    pub audio: AudioBackend,
+    pub render: RenderMode,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            palette: Default::default(),
+            colors: Default::default(),
+            keys: Default::default(),
+            release: keystate::RELEASE,
+            audio: Default::default(),
+            render: Default::default(),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Peripheral {
     term: Terminal,
     buzzer: Buzzer,
 }
 
+impl Peripheral {
+    /// Fails if the audio device cannot be opened.
+    pub fn new(options: &Options) -> io::Result<Peripheral> {
+        let buzzer = Buzzer::new(options.audio)?;
+        let mut term = Terminal::default();
+        term.set_depth(options.colors);
+        term.set_palette(&options.palette);
+        term.set_key_map(options.keys.clone());
+        term.set_release(options.release);
+        term.set_render(options.render);
+        Ok(Peripheral { term, buzzer })
+    }
+
+    /// Update the debugger pane, shown from the next frame on.
+    pub fn show_debugger(&mut self, chip: &Chip, dbg: &Debugger) {
+        self.term.show_debugger(chip, dbg);
+    }
+
+    /// Commands typed since last asked.
+    pub fn commands(&mut self) -> Vec<Command> {
+        self.term.commands()
+    }
+
+    /// Show `text` at the bottom for a few seconds.
+    pub fn show_message(&mut self, text: &str) {
+        self.term.show_message(text);
+    }
+
+    /// Whether Backspace is held to rewind.
+    pub fn rewinding(&self) -> bool {
+        self.term.rewinding()
+    }
+}
+
 impl Timer for Peripheral {
     fn pump(&mut self) -> bool {
         const NANOS_PER_FRAME: u32 = 1_000_000_000 / 60;
@@ -25,13 +110,8 @@ impl Timer for Peripheral {
 }
 
 impl Video for Peripheral {
-    fn clear(&mut self) {
-        self.term.clear();
-    }
-    fn draw(&mut self, x: isize, y: isize, sprite: &[u8]) -> bool {
-        self.term.flip_sprites(x, y, sprite)
-    }
-    fn present(&self) {
+    fn present(&mut self, screen: &Framebuffer) {
+        self.term.set_screen(screen);
         self.term.paint();
     }
 }
@@ -40,15 +120,20 @@ impl Audio for Peripheral {
     fn buzz(&mut self, on: bool) {
         self.buzzer.buzz(on);
     }
+    fn pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.buzzer.pattern(pattern, pitch);
+    }
 }
 
 impl Input for Peripheral {
     fn keydown(&self, which: usize) -> bool {
         self.term.keydown(which)
     }
+}
 
-    fn key(&self) -> Option<u8> {
-        self.term.key()
+impl Machine for Peripheral {
+    /// There is no COSMAC VIP code to run here.
+    fn sys(&mut self, _addr: u16) {
     }
 }
 
@@ -0,0 +1,187 @@
+//! Colors of the pixels, and how many colors the terminal shows.
+
+use std::env;
+use rustbox::Color;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let p = Palette::parse("black,green").unwrap();
+        assert_eq!(p.colors, [BLACK, GREEN, GREEN, GREEN]);
+        let p = Palette::parse("black,green,red,#ffcc00").unwrap();
+        assert_eq!(p.color(3), Rgb(0xff, 0xcc, 0x00));
+        assert_eq!(Palette::parse("amber"), Palette::THEMES.iter().find(|t| t.0 == "amber").map(|t| t.1));
+        assert!(Palette::parse("black").is_none());
+        assert!(Palette::parse("black,purple").is_none());
+        assert!(Palette::parse("black,#ffcc0").is_none());
+    }
+
+    #[test]
+    fn depth() {
+        assert_eq!(Depth::Colors8.code(GREEN), Color::Green as u16);
+        assert_eq!(Depth::Colors8.code(Rgb(0xff, 0xb0, 0x00)), Color::Yellow as u16, "amber");
+        assert_eq!(Depth::Colors256.code(Rgb(0, 0, 0)), 16);
+        assert_eq!(Depth::Colors256.code(Rgb(0xff, 0xcc, 0x00)), 220);
+        assert_eq!(Depth::Colors256.code(Rgb(0x80, 0x80, 0x80)), 244);
+        assert_eq!(Depth::Truecolor.code(Rgb(0xff, 0xcc, 0x00)), 220, "drawn in 256 colors first");
+    }
+}
+
+/// A color by its red, green and blue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+pub const BLACK: Rgb = Rgb(0x00, 0x00, 0x00);
+const GREEN: Rgb = Rgb(0x00, 0xcd, 0x00);
+pub const WHITE: Rgb = Rgb(0xe5, 0xe5, 0xe5);
+
+/// Color names accepted by `Palette::parse`, the colors every terminal
+/// shows, as xterm shows them.
+pub const COLORS: &[(&str, Color, Rgb)] = &[
+    ("black", Color::Black, BLACK),
+    ("red", Color::Red, Rgb(0xcd, 0x00, 0x00)),
+    ("green", Color::Green, GREEN),
+    ("yellow", Color::Yellow, Rgb(0xcd, 0xcd, 0x00)),
+    ("blue", Color::Blue, Rgb(0x00, 0x00, 0xee)),
+    ("magenta", Color::Magenta, Rgb(0xcd, 0x00, 0xcd)),
+    ("cyan", Color::Cyan, Rgb(0x00, 0xcd, 0xcd)),
+    ("white", Color::White, WHITE),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// Indexed by the planes of a pixel:
+    /// `[0]` for OFF.
+    /// `[1]` for ON in the first plane.
+    /// `[2]` for ON in the second plane.
+    /// `[3]` for ON in both.
+    colors: [Rgb; 4],
+}
+
+impl Palette {
+    /// Palettes by name, in the order the palette hotkey goes through.
+    pub const THEMES: &'static [(&'static str, Palette)] = &[
+        ("default", Palette { colors: [WHITE, COLORS[4].2, COLORS[1].2, COLORS[5].2] }),
+        ("phosphor", Palette { colors: [BLACK, Rgb(0x33, 0xff, 0x33), Rgb(0x11, 0x88, 0x11), Rgb(0xaa, 0xff, 0xaa)] }),
+        ("amber", Palette { colors: [BLACK, Rgb(0xff, 0xb0, 0x00), Rgb(0x99, 0x5f, 0x00), Rgb(0xff, 0xdd, 0x77)] }),
+        // Background, fill, fill2 and blend of Octo.
+        ("octo", Palette { colors: [Rgb(0x99, 0x66, 0x00), Rgb(0xff, 0xcc, 0x00), Rgb(0xff, 0x66, 0x00), Rgb(0x66, 0x22, 0x00)] }),
+        ("contrast", Palette { colors: [BLACK, Rgb(0xff, 0xff, 0xff), Rgb(0xff, 0xff, 0x00), Rgb(0x00, 0xff, 0xff)] }),
+    ];
+
+    /// Parse a theme name, or comma separated colors, as names or
+    /// `#rrggbb`: off and on, or off and each combination of the XO-CHIP
+    /// planes.
+    pub fn parse(spec: &str) -> Option<Palette> {
+        if let Some(&(_, theme)) = Palette::THEMES.iter().find(|&&(n, _)| n == spec) {
+            return Some(theme);
+        }
+        let colors = spec.split(',').map(parse_color).collect::<Option<Vec<_>>>()?;
+        match colors.len() {
+            2 => Some(Palette { colors: [colors[0], colors[1], colors[1], colors[1]] }),
+            4 => Some(Palette { colors: [colors[0], colors[1], colors[2], colors[3]] }),
+            _ => None,
+        }
+    }
+
+    /// Color of a pixel with the given planes on.
+    pub fn color(&self, planes: u8) -> Rgb {
+        self.colors[planes as usize & 3]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::THEMES[0].1
+    }
+}
+
+/// A name in `COLORS` or `#rrggbb`.
+fn parse_color(text: &str) -> Option<Rgb> {
+    if let Some(hex) = text.strip_prefix('#') {
+        if hex.len() != 6 { return None }
+        let c = u32::from_str_radix(hex, 16).ok()?;
+        return Some(Rgb((c >> 16) as u8, (c >> 8) as u8, c as u8));
+    }
+    COLORS.iter().find(|&&(n, _, _)| n == text).map(|&(_, _, c)| c)
+}
+
+/// How many colors the terminal shows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    /// Truecolor if `COLORTERM` tells so, 256 colors if `TERM` does,
+    /// else 8.
+    #[default]
+    Auto,
+    Colors8,
+    Colors256,
+    /// 24-bit colors. Termbox goes no further than 256, so the pixels
+    /// are drawn again over them, see `truecolor`.
+    Truecolor,
+}
+
+impl Depth {
+    /// Depths by name, for users to choose from.
+    pub const NAMES: &'static [(&'static str, Depth)] = &[
+        ("auto", Depth::Auto),
+        ("8", Depth::Colors8),
+        ("256", Depth::Colors256),
+        ("truecolor", Depth::Truecolor),
+    ];
+
+    /// Look up a depth by its name in `NAMES`.
+    pub fn from_name(name: &str) -> Option<Depth> {
+        Depth::NAMES.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, d)| d)
+    }
+
+    /// `Auto` told from the environment.
+    pub fn detect(self) -> Depth {
+        if self != Depth::Auto { return self }
+        let term = env::var("TERM").unwrap_or_default();
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            Depth::Truecolor
+        } else if term.contains("256color") {
+            Depth::Colors256
+        } else {
+            Depth::Colors8
+        }
+    }
+
+    /// The termbox color of the output mode nearest to `c`, of the 256
+    /// for `Truecolor`. Not for `Auto`.
+    pub fn code(self, c: Rgb) -> u16 {
+        let distance = |o: Rgb| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(c.0, o.0) + d(c.1, o.1) + d(c.2, o.2)
+        };
+        match self {
+            Depth::Auto | Depth::Colors8 => {
+                COLORS.iter().min_by_key(|&&(_, _, o)| distance(o)).unwrap().1 as u16
+            },
+            // The 6x6x6 color cube from 16 and the grays from 232, the
+            // first 16 colors varying from terminal to terminal.
+            Depth::Colors256 | Depth::Truecolor => (16..256).min_by_key(|&n| distance(xterm_color(n))).unwrap(),
+        }
+    }
+}
+
+/// Color `n` of the xterm 256 colors, from 16.
+fn xterm_color(n: u16) -> Rgb {
+    const LEVELS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+    match n {
+        16..=231 => {
+            let n = n as usize - 16;
+            Rgb(LEVELS[n / 36], LEVELS[n / 6 % 6], LEVELS[n % 6])
+        },
+        _ => {
+            let gray = 8 + 10 * (n as u8 - 232);
+            Rgb(gray, gray, gray)
+        },
+    }
+}
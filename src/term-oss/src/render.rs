@@ -0,0 +1,193 @@
+//! Laying the pixels of the screen out in terminal cells, some modes
+//! drawing several pixels per cell with block or Braille characters.
+
+use chip::Framebuffer;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(rows: &[Vec<Cell>]) -> Vec<String> {
+        rows.iter().map(|row| row.iter().map(|c| c.ch).collect()).collect()
+    }
+
+    #[test]
+    fn fit() {
+        assert_eq!(Mode::Auto.fit(64, 32, 200, 50), Ok(Mode::Wide));
+        assert_eq!(Mode::Auto.fit(64, 32, 80, 24), Ok(Mode::HalfBlock));
+        assert_eq!(Mode::Auto.fit(128, 64, 80, 24), Ok(Mode::Braille));
+        assert_eq!(Mode::Auto.fit(128, 64, 40, 10), Err((64, 16)));
+        assert_eq!(Mode::Quadrant.fit(128, 64, 200, 50), Ok(Mode::Quadrant));
+        assert_eq!(Mode::Wide.fit(64, 32, 80, 24), Err((128, 32)));
+    }
+
+    #[test]
+    fn blocks() {
+        let mut fb = Framebuffer::new(4, 4);
+        fb.draw(1, 0, 0, &[0b1000_0000, 0b0100_0000, 0b1100_0000, 0b1111_0000], false);
+        assert_eq!(text(&cells(Mode::HalfBlock, &fb)), ["▀▄  ", "██▄▄"]);
+        assert_eq!(text(&cells(Mode::Quadrant, &fb)), ["▚ ", "█▄"]);
+        assert_eq!(text(&cells(Mode::Braille, &fb)), ["\u{28f5}\u{28c0}"]);
+        assert_eq!(text(&cells(Mode::Wide, &fb))[0], "        ");
+        assert_eq!(cells(Mode::Wide, &fb)[0][..3], [Cell { ch: ' ', fg: 1, bg: 1 }, Cell { ch: ' ', fg: 1, bg: 1 },
+                                                    Cell { ch: ' ', fg: 0, bg: 0 }]);
+    }
+
+    #[test]
+    fn colors() {
+        // Off, both planes, then the second plane, both planes.
+        let mut fb = Framebuffer::new(2, 2);
+        fb.draw(3, 1, 0, &[0b1000_0000, 0b1000_0000], false);
+        fb.draw(2, 0, 1, &[0b1000_0000], false);
+        assert_eq!(cells(Mode::Quadrant, &fb)[0][0], Cell { ch: '▟', fg: 3, bg: 0 });
+        assert_eq!(cells(Mode::HalfBlock, &fb)[0], [Cell { ch: '▄', fg: 2, bg: 0 }, Cell { ch: '█', fg: 3, bg: 0 }]);
+
+        // None off: one plane over the other.
+        let mut fb = Framebuffer::new(1, 2);
+        fb.draw(1, 0, 0, &[0b1000_0000], false);
+        fb.draw(2, 0, 1, &[0b1000_0000], false);
+        assert_eq!(cells(Mode::HalfBlock, &fb)[0], [Cell { ch: '▄', fg: 2, bg: 1 }]);
+    }
+}
+
+/// Quadrant blocks, by the pixels lit: bit 0 top left, 1 top right,
+/// 2 bottom left, 3 bottom right.
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+/// Braille dots, by pixel in a 2x4 cell, row by row.
+const DOTS: [u32; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The biggest pixels fitting the terminal, the first mode that
+    /// fits of the ones below.
+    #[default]
+    Auto,
+    /// A pixel is two cells side by side, square.
+    Wide,
+    /// Two pixels one above the other per cell, square.
+    HalfBlock,
+    /// 2x2 pixels per cell.
+    Quadrant,
+    /// 2x4 pixels per cell, as Braille dots.
+    Braille,
+}
+
+impl Mode {
+    /// Modes by name, for users to choose from.
+    pub const NAMES: &'static [(&'static str, Mode)] = &[
+        ("auto", Mode::Auto),
+        ("wide", Mode::Wide),
+        ("half", Mode::HalfBlock),
+        ("quadrant", Mode::Quadrant),
+        ("braille", Mode::Braille),
+    ];
+
+    /// Look up a mode by its name in `NAMES`.
+    pub fn from_name(name: &str) -> Option<Mode> {
+        Mode::NAMES.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, m)| m)
+    }
+
+    /// Cells taken by `w`x`h` pixels. Not for `Auto`.
+    pub fn size(self, w: usize, h: usize) -> (usize, usize) {
+        match self {
+            Mode::Auto | Mode::Wide => (w * 2, h),
+            Mode::HalfBlock => (w, h.div_ceil(2)),
+            Mode::Quadrant => (w.div_ceil(2), h.div_ceil(2)),
+            Mode::Braille => (w.div_ceil(2), h.div_ceil(4)),
+        }
+    }
+
+    /// The mode to draw `w`x`h` pixels in within `cw`x`ch` cells, or the
+    /// cells needed at least if none fits.
+    pub fn fit(self, w: usize, h: usize, cw: usize, ch: usize) -> Result<Mode, (usize, usize)> {
+        let modes: &[Mode] = match self {
+            Mode::Auto => &[Mode::Wide, Mode::HalfBlock, Mode::Quadrant, Mode::Braille],
+            _ => &[self],
+        };
+        let fits = |&&mode: &&Mode| {
+            let (mw, mh) = mode.size(w, h);
+            mw <= cw && mh <= ch
+        };
+        modes.iter().find(fits).cloned().ok_or_else(|| modes[modes.len() - 1].size(w, h))
+    }
+}
+
+/// A character drawn with the colors of the given planes, see
+/// `Framebuffer::pixel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: u8,
+    pub bg: u8,
+}
+
+/// Rows of cells showing `screen` in `mode`, not `Auto`.
+///
+/// A cell only has two colors: the pixels on in the planes of most of
+/// them are lit over the pixels off, or over the pixels on in other
+/// planes if none is off, and the rest are lit along.
+pub fn cells(mode: Mode, screen: &Framebuffer) -> Vec<Vec<Cell>> {
+    let (pw, ph) = match mode {
+        Mode::Auto | Mode::Wide => (1, 1),
+        Mode::HalfBlock => (1, 2),
+        Mode::Quadrant => (2, 2),
+        Mode::Braille => (2, 4),
+    };
+    let (w, h) = (screen.width(), screen.height());
+    let mut rows = vec![];
+    for cy in (0..h).step_by(ph) {
+        let mut row = vec![];
+        for cx in (0..w).step_by(pw) {
+            let mut pixels = [None; 8];
+            for (i, px) in pixels.iter_mut().take(pw * ph).enumerate() {
+                let (x, y) = (cx + i % pw, cy + i / pw);
+                if x < w && y < h {
+                    *px = Some(screen.pixel(x, y));
+                }
+            }
+            let (fg, bg, lit) = two_colors(&pixels);
+            let cell = |ch| Cell { ch, fg, bg };
+            match mode {
+                Mode::Auto | Mode::Wide => {
+                    let px = if lit != 0 { fg } else { bg };
+                    row.extend(&[Cell { ch: ' ', fg: px, bg: px }; 2]);
+                },
+                Mode::HalfBlock => row.push(cell([' ', '▀', '▄', '█'][lit as usize])),
+                Mode::Quadrant => row.push(cell(QUADRANTS[lit as usize])),
+                Mode::Braille => {
+                    let dots = (0..8).filter(|&i| lit & 1 << i != 0).map(|i| DOTS[i]).sum::<u32>();
+                    row.push(cell(char::from_u32(0x2800 + dots).unwrap()));
+                },
+            }
+        }
+        rows.push(row);
+    }
+    rows
+}
+
+/// The colors of the lit pixels and of the background, and which pixels
+/// are lit, bit N for pixel N, of the pixels of a cell.
+fn two_colors(pixels: &[Option<u8>; 8]) -> (u8, u8, u8) {
+    let mut count = [0; 4];
+    for &px in pixels.iter().flatten() {
+        count[px as usize & 3] += 1;
+    }
+    let most = |skip: usize| (1..4).filter(|&p| p != skip && count[p] > 0).max_by_key(|&p| (count[p], p));
+    let fg = most(0).unwrap_or(0);
+    let bg = match most(fg) {
+        Some(other) if count[0] == 0 => other,
+        _ => 0,
+    };
+    let mut lit = 0;
+    for (i, &px) in pixels.iter().enumerate() {
+        if px.is_some_and(|px| px as usize & 3 != bg) {
+            lit |= 1 << i;
+        }
+    }
+    (fg as u8, bg as u8, lit)
+}
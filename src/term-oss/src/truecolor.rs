@@ -0,0 +1,127 @@
+//! 24-bit colors, which termbox knows nothing of. The screen is drawn
+//! through termbox in the nearest of 256 colors as usual, then its cells
+//! are written again in their true colors straight to the terminal,
+//! after termbox presented them.
+//!
+//! Termbox keeps track of the cursor and colors it last sent, so the
+//! cells are written between saving and restoring both (`DECSC` and
+//! `DECRC`). A cell is only written again once changed, or once termbox
+//! wrote over it.
+
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::slice;
+use termbox_sys::{self, RawCell};
+use palette::Rgb;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn raw(ch: char, fg: u16, bg: u16) -> RawCell {
+        RawCell { ch: ch as u32, fg, bg }
+    }
+
+    #[test]
+    fn changes() {
+        const GREEN: Rgb = Rgb(0x33, 0xff, 0x33);
+        let cell = |x, ch| Cell { x, y: 1, ch, fg: (2, GREEN), bg: (1, Rgb(0, 0, 0)) };
+        let mut t = Truecolor::default();
+        let mut shown = vec![raw(' ', 0, 0); 8];
+        shown[4] = raw('▀', 2, 1);
+        shown[5] = raw('▄', 2, 1);
+        assert_eq!(t.changes(&[cell(0, '▀'), cell(1, '▄')], &shown, 4),
+                   "\x1b7\x1b[2;1H\x1b[0;38;2;51;255;51;48;2;0;0;0m▀▄\x1b8");
+        assert_eq!(t.changes(&[cell(0, '▀'), cell(1, '▄')], &shown, 4), "", "unchanged");
+
+        // Covered, then shown again.
+        shown[5] = raw('x', 7, 0);
+        assert_eq!(t.changes(&[cell(0, '▀'), cell(1, '▄')], &shown, 4), "");
+        shown[5] = raw('▄', 2, 1);
+        assert_eq!(t.changes(&[cell(0, '▀'), cell(1, '▄')], &shown, 4),
+                   "\x1b7\x1b[2;2H\x1b[0;38;2;51;255;51;48;2;0;0;0m▄\x1b8");
+    }
+}
+
+/// A cell of the screen, with its colors as given to termbox and as
+/// they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub x: usize,
+    pub y: usize,
+    pub ch: char,
+    pub fg: (u16, Rgb),
+    pub bg: (u16, Rgb),
+}
+
+#[derive(Default)]
+pub struct Truecolor {
+    /// The terminal termbox uses, once opened.
+    tty: Option<File>,
+    /// Size of the terminal when last presented.
+    size: (usize, usize),
+    /// Cells shown in true colors, by position.
+    written: HashMap<(usize, usize), Cell>,
+}
+
+impl Truecolor {
+    /// Write `cells` in true colors, right after `RustBox::present`.
+    pub fn present(&mut self, cells: &[Cell]) {
+        // Termbox is initialized along with the terminal.
+        let (w, h) = unsafe { (termbox_sys::tb_width() as usize, termbox_sys::tb_height() as usize) };
+        if (w, h) != self.size {
+            // Redrawn by termbox from scratch.
+            self.written.clear();
+            self.size = (w, h);
+        }
+        let shown = unsafe { slice::from_raw_parts(termbox_sys::tb_cell_buffer(), w * h) };
+        let out = self.changes(cells, shown, w);
+        if out.is_empty() { return }
+        if self.tty.is_none() {
+            self.tty = OpenOptions::new().write(true).open("/dev/tty").ok();
+        }
+        if let Some(ref mut tty) = self.tty {
+            let _ = tty.write_all(out.as_bytes());
+        }
+    }
+
+    /// The output writing the `cells` not written yet, of the ones
+    /// termbox shows as given in `shown`, `w` cells a row.
+    fn changes(&mut self, cells: &[Cell], shown: &[RawCell], w: usize) -> String {
+        let mut out = String::new();
+        let mut written = HashMap::new();
+        // Where the cursor is and the colors set after the last cell
+        // written.
+        let (mut cursor, mut colors) = (None, None);
+        for &cell in cells {
+            let raw = match shown.get(cell.y * w + cell.x) {
+                Some(raw) if cell.x < w => raw,
+                _ => continue,
+            };
+            // Covered by something else.
+            if (raw.ch, raw.fg, raw.bg) != (cell.ch as u32, cell.fg.0, cell.bg.0) { continue }
+            written.insert((cell.x, cell.y), cell);
+            if self.written.get(&(cell.x, cell.y)) == Some(&cell) { continue }
+            if out.is_empty() {
+                out.push_str("\x1b7");
+            }
+            if cursor != Some((cell.x, cell.y)) {
+                let _ = write!(out, "\x1b[{};{}H", cell.y + 1, cell.x + 1);
+            }
+            if colors != Some((cell.fg.1, cell.bg.1)) {
+                let (Rgb(fr, fg, fb), Rgb(br, bg, bb)) = (cell.fg.1, cell.bg.1);
+                let _ = write!(out, "\x1b[0;38;2;{};{};{};48;2;{};{};{}m", fr, fg, fb, br, bg, bb);
+            }
+            out.push(cell.ch);
+            cursor = Some((cell.x + 1, cell.y));
+            colors = Some((cell.fg.1, cell.bg.1));
+        }
+        if !out.is_empty() {
+            out.push_str("\x1b8");
+        }
+        self.written = written;
+        out
+    }
+}
@@ -6,167 +6,250 @@
 extern crate time;
 extern crate rustbox;
 extern crate unicode_width;
+extern crate termbox_sys;
 
 use std::iter;
+use std::os::raw::c_int;
 use std::time::Duration;
-use rustbox::{RustBox, Style, Color, Event};
+use rustbox::{RustBox, Style, Event};
 use rustbox::keyboard::Key;
 use unicode_width::UnicodeWidthStr;
-use modulo::Modulo;
 use time::precise_time_ns as now_ns;
-use self::from_nanos::FromNanos;    // add Duration::from_nanos(u64);
-use self::key_map::key_from_char;
+use keymap::KeyMap;
+use keystate::KeyState;
+use kitty::{self, Action, Sequence};
+use palette::{self, Palette, Depth};
+use render::{self, Mode};
+use truecolor::{self, Truecolor};
+use debugger::{self, Pane};
+use Command;
+use chip::{Chip, Debugger, Framebuffer};
+use std::mem;
 
 #[cfg(test)]
 mod test {
     use super::*;
     use std::time::Duration;
 
+    const SPRITE: &[u8] = &[
+        0b10100101,
+        0b11000011,
+        0b00111100,
+        0b11111111,
+        0b11000011];
+
     #[test]
     fn term() {
         let mut t: Terminal = Default::default();
-        t.flip_sprites(10, 20, &[
-                       0b10100101,
-                       0b11000011,
-                       0b00111100,
-                       0b11111111,
-                       0b11000011]);
+        let mut fb = Framebuffer::default();
+        fb.draw(1, 10, 20, SPRITE, false);
+        t.set_screen(&fb);
         t.paint();
         if t.pump_events(Duration::from_millis(500)) { return }
         for _ in 0..8 {
-            t.flip_sprites(10, 23, &[
-                           0b10100101,
-                           0b11000011,
-                           0b00111100,
-                           0b11111111,
-                           0b11000011]);
+            fb.draw(1, 10, 23, SPRITE, false);
+            t.set_screen(&fb);
             t.paint();
             if t.pump_events(Duration::from_millis(100)) { return }
             if t.keydown(5) { break }
         }
         if t.pump_events(Duration::from_millis(500)) { return }
         for x in 0..20 {
-            let x = x as isize - 10isize;
-            t.flip_sprites(x, 23, &[
-                           0b10100101,
-                           0b11000011,
-                           0b00111100,
-                           0b11111111,
-                           0b11000011]);
+            // From 10 pixels left of the screen, wrapped around.
+            let x = (x + 54) % 64;
+            fb.draw(1, x, 23, SPRITE, false);
+            t.set_screen(&fb);
             t.paint();
-            t.flip_sprites(x, 23, &[
-                           0b10100101,
-                           0b11000011,
-                           0b00111100,
-                           0b11111111,
-                           0b11000011]);
+            fb.draw(1, x, 23, SPRITE, false);
             if t.pump_events(Duration::from_millis(100)) { return }
             if t.keydown(0) { break }
         }
-        t.clear();
+        t.set_screen(&Framebuffer::default());
         t.paint();
-        if t.pump_events(Duration::from_millis(1000)) { return }
+        t.pump_events(Duration::from_millis(1000));
     }
 }
 
 
-mod key_map {
-    //                             0123456789ABCDEF
-    const KEY_MAP: &'static str = "x123qweasdzcvfr4";
-    // QWERTY KEYBOARD               HEX KEYBOARD
-    //     1 2 3 4                     1 2 3 F
-    //     q w e r                     4 5 6 E
-    //     a s d f                     7 8 9 D
-    //     z x c v                     A 0 B C
-
-    pub fn key_from_char(ch: char) -> Option<usize> {
-        KEY_MAP.chars()
-            .enumerate()
-            .filter_map(|(i, c)| select(c == ch, i))
-            .next()
-    }
-
-    fn select<T>(pred: bool, x: T) -> Option<T> {
-        if pred { Some(x) } else { None }
-    }
-}
+/// Number of save state slots.
+const SLOTS: u8 = 9;
 
+/// A held key repeats at least this often, after the first delay.
+const REWIND_NANOS: u64 = 150_000_000;
 
-/// StyleComplex(style, fg, bg);
-struct StyleComplex(Style, Color, Color);
+/// StyleComplex(style, fg, bg), the colors as termbox takes them in the
+/// output mode, see `Depth::code`.
+struct StyleComplex(Style, u16, u16);
 
 pub struct Terminal {
     rb: RustBox,
-    /// 1 pixel width = 2 chars horizontally
-    pixel_w: usize,
-    /// 1 pixel height = 1 char vertically
-    pixel_h: usize,
-    /// Screen of CHIP-8.
-    screen: Vec<bool>,
+    /// Screen of CHIP-8 as last presented.
+    screen: Framebuffer,
+    /// How the pixels are laid out in cells.
+    render: Mode,
     /// keydown status in a frame.
     keydowns: [bool; 16],
+    held: KeyState,
+    keys: KeyMap,
+    /// Debugger pane.
+    pane: Pane,
+    pane_lines: Option<Vec<(String, bool)>>,
+    /// Typed since last asked.
+    commands: Vec<Command>,
+    /// Save state slot, `1` ~ `SLOTS`.
+    slot: u8,
+    /// Shown at the bottom until the time in ns.
+    message: Option<(String, u64)>,
+    palette: Palette,
+    /// Index of the palette in `Palette::THEMES`, if there.
+    theme: Option<usize>,
+    /// Colors shown, not `Auto`.
+    depth: Depth,
+    truecolor: Truecolor,
+    /// Rewinding until the time in ns, renewed by the repeats of the
+    /// held key.
+    rewind_until: u64,
 
     text_style: StyleComplex,
+    /// Indexed by the planes of a pixel:
     /// `[0]` for style of OFF.
-    /// `[1]` for style of ON.
-    cell_styles: [StyleComplex; 2],
+    /// `[1]` for style of ON in the first plane.
+    /// `[2]` for style of ON in the second plane.
+    /// `[3]` for style of ON in both.
+    cell_styles: [StyleComplex; 4],
 }
 
 impl Terminal {
-    pub fn paint(&self) {
+    pub fn paint(&mut self) {
         let rb = &self.rb;
         rb.clear();
+        // Screen cells to show in true colors.
+        let mut cells = vec![];
 
-        let tw = rb.width();
         let th = rb.height();
-        let w = self.pixel_w * 2;
-        let h = self.pixel_h;
-
-        if tw < w || th < h {   // terminal size too small
-            self.print_centered(&format!("{}x{} is too small\n\
-                                         At least {}x{} is required",
-                                         rb.width(),
-                                         rb.height(),
-                                         self.pixel_w * 2,
-                                         self.pixel_h));
-            self.draw_border();
-        } else {                // terminal size big enough
-            let     x = (tw - w) / 2;   // "no underflow" guaranteed by the outer "if".
-            let mut y = (th - h) / 2;
-            for row in self.screen.chunks(self.pixel_w) {
-                let mut x = x;
-                for st in row.iter().map(|&on| &self.cell_styles[on as usize]) {
-                    rb.print(x, y, st.0, st.1, st.2, "  ");
-                    x += 2;
+        let (pw, ph) = (self.screen.width(), self.screen.height());
+        // The pane goes next to the screen if there is room, over it if not.
+        let beside = match self.pane_lines {
+            Some(_) => rb.width().saturating_sub(debugger::WIDTH),
+            None => rb.width(),
+        };
+        let fit = self.render.fit(pw, ph, beside, th).map(|mode| (mode, beside))
+            .or_else(|_| self.render.fit(pw, ph, rb.width(), th).map(|mode| (mode, rb.width())));
+
+        match fit {
+            Err((w, h)) => {    // terminal size too small
+                self.print_centered(&format!("{}x{} is too small\n\
+                                             At least {}x{} is required",
+                                             rb.width(),
+                                             rb.height(),
+                                             w,
+                                             h));
+                self.draw_border();
+            },
+            Ok((mode, tw)) => { // terminal size big enough
+                let (w, h) = mode.size(pw, ph);
+                let x = (tw - w) / 2;   // "no underflow" guaranteed by `fit`.
+                let y = (th - h) / 2;
+                for (y, row) in (y..).zip(render::cells(mode, &self.screen)) {
+                    for (x, cell) in (x..).zip(row) {
+                        let (fg, bg) = (&self.cell_styles[cell.fg as usize], &self.cell_styles[cell.bg as usize]);
+                        self.print_char(x, y, bg.0, fg.2, bg.2, cell.ch);
+                        if self.depth == Depth::Truecolor {
+                            cells.push(truecolor::Cell {
+                                x, y, ch: cell.ch,
+                                fg: (fg.2, self.palette.color(cell.fg)),
+                                bg: (bg.2, self.palette.color(cell.bg)),
+                            });
+                        }
+                    }
                 }
-                y += 1;
+            },
+        }
+        if let Some(ref lines) = self.pane_lines {
+            self.draw_pane(lines);
+        }
+        if let Some((ref text, until)) = self.message {
+            if now_ns() < until {
+                let st = &self.text_style;
+                self.print(0, th.saturating_sub(1), st.0, st.1, st.2, text);
             }
         }
         rb.present();
+        if self.depth == Depth::Truecolor {
+            self.truecolor.present(&cells);
+        }
     }
 
-    pub fn clear(&mut self) {
-        for x in self.screen.iter_mut() {
-            *x = false;
-        }
+    /// Show the debugger pane while paused, or always if asked to.
+    pub fn show_debugger(&mut self, chip: &Chip, dbg: &Debugger) {
+        self.pane_lines = if self.pane.visible || dbg.is_paused() {
+            Some(self.pane.lines(chip, dbg))
+        } else {
+            None
+        };
     }
 
-    /// Returns true if anything has been flipped to false.
-    /// Returns false if nothing has been flipped to false.
-    pub fn flip_sprites(&mut self, x: isize, y: isize, sprites: &[u8]) -> bool {
-        let mut flip_to_false = false;
-        let mut y = y;
-        for &sprite in sprites {
-            flip_to_false = self.flip_sprite(x, y, sprite) || flip_to_false;
-            y += 1;
+    /// Commands typed since last asked.
+    pub fn commands(&mut self) -> Vec<Command> {
+        mem::take(&mut self.commands)
+    }
+
+    /// Show `text` at the bottom for a few seconds.
+    pub fn show_message(&mut self, text: &str) {
+        const NANOS: u64 = 2_000_000_000;
+        self.message = Some((text.to_string(), now_ns() + NANOS));
+    }
+
+    /// Whether the rewind key is held.
+    pub fn rewinding(&self) -> bool {
+        now_ns() < self.rewind_until
+    }
+
+    /// Color the pixels with `palette`.
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette;
+        self.theme = Palette::THEMES.iter().position(|&(_, theme)| theme == *palette);
+        let depth = self.depth;
+        self.text_style = StyleComplex(rustbox::RB_BOLD, depth.code(palette::WHITE), depth.code(palette::BLACK));
+        for (planes, st) in self.cell_styles.iter_mut().enumerate() {
+            st.2 = depth.code(palette.color(planes as u8));
         }
-        flip_to_false
+    }
+
+    /// Show as many colors as `depth`, told from the environment if `Auto`.
+    pub fn set_depth(&mut self, depth: Depth) {
+        self.depth = depth.detect();
+        let mode = match self.depth {
+            Depth::Colors256 | Depth::Truecolor => termbox_sys::TB_OUTPUT_256,
+            _ => termbox_sys::TB_OUTPUT_NORMAL,
+        };
+        // Termbox is initialized along with `rb`.
+        unsafe { termbox_sys::tb_select_output_mode(mode); }
+        let palette = self.palette;
+        self.set_palette(&palette);
+    }
+
+    pub fn set_key_map(&mut self, keys: KeyMap) {
+        self.keys = keys;
+    }
+
+    /// How long a key stays held after it was last sent, unless the
+    /// terminal reports releases.
+    pub fn set_release(&mut self, release: Duration) {
+        self.held.set_release(release);
+    }
+
+    pub fn set_render(&mut self, render: Mode) {
+        self.render = render;
+    }
+
+    /// Show `screen` from the next paint on.
+    pub fn set_screen(&mut self, screen: &Framebuffer) {
+        self.screen.clone_from(screen);
     }
 
     /// Returns true for quit-request.
     pub fn pump_events(&mut self, frame_time: Duration) -> bool {
-        self.keydowns = [false; 16];
-
         let mut remaining = frame_time;
         loop {
             let start = now_ns();
@@ -176,9 +259,10 @@ impl Terminal {
             if self.handle_event(ev) { return true }
 
             if remaining <= dura { break }
-            remaining = remaining - dura;
+            remaining -= dura;
         }
 
+        self.keydowns = self.held.frame(now_ns());
         false
     }
 
@@ -186,39 +270,20 @@ impl Terminal {
         self.keydowns[which]
     }
 
-    pub fn key(&self) -> Option<u8> {
-        loop {
-            let ev = self.rb.poll_event(false).unwrap();
-            match ev {
-                Event::ResizeEvent(..) => (),
-                Event::MouseEvent(..) => (),
-                Event::KeyEvent(Key::Esc) => return None,
-                Event::KeyEvent(Key::Char(ch)) => {
-                    if let Some(k) = key_from_char(ch) {
-                        return Some(k as u8);
-                    }
-                },
-                Event::KeyEvent(_) => (),
-                _ => unreachable!(),
-            }
-            self.paint();
-        }
-    }
-
-
-    /// Assume self.keydowns == [false; 16] on the start of a frame.
     /// Returns true for quit-request.
     fn handle_event(&mut self, ev: Event) -> bool {
         match ev {
             Event::ResizeEvent(..) => (),
             Event::MouseEvent(..) => (),
-            Event::KeyEvent(Key::Esc) => return true,
-            Event::KeyEvent(Key::Char(ch)) => {
-                if let Some(k) = key_from_char(ch) {
-                    self.keydowns[k] = true;
-                }
+            Event::KeyEvent(Key::Esc) => match self.read_escape() {
+                None => return true,
+                Some(seq) => match kitty::parse(&seq) {
+                    Some(Sequence::Key(key, action)) => return self.handle_key(key, action),
+                    Some(Sequence::Flags(_)) => self.held.report_releases(),
+                    None => (),
+                },
             },
-            Event::KeyEvent(_) => (),
+            Event::KeyEvent(key) => return self.handle_key(key, Action::Press),
             Event::NoEvent => (),
             _ => unreachable!(),
         }
@@ -226,46 +291,103 @@ impl Terminal {
         false
     }
 
+    /// Returns true for quit-request.
+    fn handle_key(&mut self, key: Key, action: Action) -> bool {
+        if let Some(k) = self.keys.key(key) {
+            match action {
+                Action::Release => self.held.release(k),
+                _ => self.held.press(k, now_ns()),
+            }
+            return false;
+        }
+        if action == Action::Release { return false }
+
+        match key {
+            Key::Esc => return true,
+            Key::Backspace => self.rewind_until = now_ns() + REWIND_NANOS,
+            Key::F(1) => {
+                self.slot = self.slot % SLOTS + 1;
+                let text = format!("slot {}", self.slot);
+                self.show_message(&text);
+            },
+            Key::F(2) => self.commands.push(Command::SaveState(self.slot)),
+            Key::F(3) => self.commands.push(Command::LoadState(self.slot)),
+            Key::F(11) => {
+                let next = self.theme.map_or(0, |t| (t + 1) % Palette::THEMES.len());
+                let (name, palette) = Palette::THEMES[next];
+                self.set_palette(&palette);
+                self.show_message(&format!("palette {}", name));
+            },
+            key => {
+                let mut commands = vec![];
+                self.pane.handle_key(key, &mut commands);
+                self.commands.extend(commands.into_iter().map(Command::Debug));
+            },
+        }
+
+        false
+    }
+
+    /// After `Esc`, the rest of the control sequence it starts, after
+    /// `[`, as termbox splits the ones it does not know into characters,
+    /// all read already. `None` for the Esc key alone.
+    fn read_escape(&mut self) -> Option<String> {
+        let mut seq = String::new();
+        match self.rb.peek_event(Duration::from_millis(0), false) {
+            Ok(Event::KeyEvent(Key::Char('['))) => (),
+            _ => return None,
+        }
+        while let Ok(Event::KeyEvent(Key::Char(ch))) = self.rb.peek_event(Duration::from_millis(0), false) {
+            seq.push(ch);
+            // Parameters, then a final character.
+            if !('0'..='?').contains(&ch) { break }
+        }
+        Some(seq)
+    }
+
     /// Only allow construction from Default trait
-    fn new(pixel_size: (usize, usize)) -> Terminal {
-        Terminal {
+    fn new() -> Terminal {
+        let mut t = Terminal {
             rb: RustBox::init(Default::default()).unwrap(),
-            pixel_w: pixel_size.0,
-            pixel_h: pixel_size.1,
-            screen: vec![false; pixel_size.0 * pixel_size.1],
+            screen: Default::default(),
+            render: Default::default(),
             keydowns: [false; 16],
-            text_style: StyleComplex(rustbox::RB_BOLD, Color::White, Color::Black),
+            held: Default::default(),
+            keys: Default::default(),
+            pane: Default::default(),
+            pane_lines: None,
+            commands: vec![],
+            slot: 1,
+            message: None,
+            palette: Default::default(),
+            theme: None,
+            depth: Depth::Colors8,
+            truecolor: Default::default(),
+            rewind_until: 0,
+            text_style: StyleComplex(rustbox::RB_BOLD, 0, 0),
             cell_styles: [
-                StyleComplex(rustbox::RB_NORMAL, Color::Black, Color::White),
-                StyleComplex(rustbox::RB_NORMAL, Color::Black, Color::Blue),
+                StyleComplex(rustbox::RB_NORMAL, 0, 0),
+                StyleComplex(rustbox::RB_NORMAL, 0, 0),
+                StyleComplex(rustbox::RB_NORMAL, 0, 0),
+                StyleComplex(rustbox::RB_NORMAL, 0, 0),
             ],
-        }
+        };
+        t.set_palette(&Default::default());
+        kitty::enable();
+        t
     }
 
-    /// Returns true if anything has been flipped to false.
-    /// Returns false if nothing has been flipped to false.
-    fn flip_sprite(&mut self, x: isize, y: isize, sprite: u8) -> bool {
-        let mut flip_to_false = false;
-        let mut x = x;
-        for i in (0..8).rev() {
-            if sprite & (1 << i) != 0 {
-                if self.flip(x, y) == false {
-                    flip_to_false = true;
-                }
-            }
-            x += 1;
+    /// Like `RustBox::print`, with colors beyond the 8 of rustbox.
+    fn print(&self, x: usize, y: usize, style: Style, fg: u16, bg: u16, text: &str) {
+        for (x, ch) in (x..).zip(text.chars()) {
+            self.print_char(x, y, style, fg, bg, ch);
         }
-        flip_to_false
     }
 
-    /// Returns the value after flipping.
-    fn flip(&mut self, x: isize, y: isize) -> bool {
-        let x = x.modulo(self.pixel_w as isize);
-        let y = y.modulo(self.pixel_h as isize);
-        let i = self.pixel_w * y + x;
-        let on = self.screen.iter_mut().nth(i).unwrap();
-        *on = !*on;
-        *on
+    fn print_char(&self, x: usize, y: usize, style: Style, fg: u16, bg: u16, ch: char) {
+        // Termbox is initialized along with `rb`, and ignores cells
+        // outside of the terminal.
+        unsafe { termbox_sys::tb_change_cell(x as c_int, y as c_int, ch as u32, fg | style.bits(), bg); }
     }
 
     /// Print text with x centered, single line only.
@@ -283,7 +405,7 @@ impl Terminal {
             }
             return;
         }
-        rb.print(x as usize + 1, y, st.0, st.1, st.2, line);        // +1 for the border
+        self.print(x as usize + 1, y, st.0, st.1, st.2, line);        // +1 for the border
     }
 
     /// Print text in the center of screen, support multiple lines,
@@ -309,10 +431,8 @@ impl Terminal {
             return;
         }
 
-        let mut y = y + 1;                                          // +1 for the border
-        for line in text.lines() {
+        for (y, line) in (y + 1..).zip(text.lines()) {              // +1 for the border
             self.print_centering_x(y as usize, line);
-            y += 1;
         }
     }
 
@@ -320,27 +440,38 @@ impl Terminal {
     fn draw_box(&self, x: usize, y: usize, w: usize, h: usize) {
         if w < 2 || h < 2 { return }
 
-        let rb = &self.rb;
         let st = &self.text_style;
 
         // x lines
         {
-            let line: String = iter::repeat("─").take(w-2).collect();
-            rb.print(x+1, y,     st.0, st.1, st.2, &line);
-            rb.print(x+1, y+h-1, st.0, st.1, st.2, &line);
+            let line = "─".repeat(w-2);
+            self.print(x+1, y,     st.0, st.1, st.2, &line);
+            self.print(x+1, y+h-1, st.0, st.1, st.2, &line);
         }
 
         // y lines
         for i in 1..h-1 {
-            rb.print(x,     y+i, st.0, st.1, st.2, "│");
-            rb.print(x+w-1, y+i, st.0, st.1, st.2, "│");
+            self.print(x,     y+i, st.0, st.1, st.2, "│");
+            self.print(x+w-1, y+i, st.0, st.1, st.2, "│");
         }
 
         // corners
-        rb.print(x,     y,     st.0, st.1, st.2, "┌");
-        rb.print(x,     y+h-1, st.0, st.1, st.2, "└");
-        rb.print(x+w-1, y,     st.0, st.1, st.2, "┐");
-        rb.print(x+w-1, y+h-1, st.0, st.1, st.2, "┘");
+        self.print(x,     y,     st.0, st.1, st.2, "┌");
+        self.print(x,     y+h-1, st.0, st.1, st.2, "└");
+        self.print(x+w-1, y,     st.0, st.1, st.2, "┐");
+        self.print(x+w-1, y+h-1, st.0, st.1, st.2, "┘");
+    }
+
+    /// Right aligned, highlighting the line under the cursor.
+    fn draw_pane(&self, lines: &[(String, bool)]) {
+        let rb = &self.rb;
+        let st = &self.text_style;
+        let x = rb.width().saturating_sub(debugger::WIDTH);
+        for (y, &(ref line, cursor)) in lines.iter().enumerate().take(rb.height()) {
+            let style = if cursor { st.0 | rustbox::RB_REVERSE } else { st.0 };
+            let text: String = line.chars().chain(iter::repeat(' ')).take(debugger::WIDTH).collect();
+            self.print(x, y, style, st.1, st.2, &text);
+        }
     }
 
     fn draw_border(&self) {
@@ -349,28 +480,14 @@ impl Terminal {
     }
 }
 
-impl Default for Terminal {
-    fn default() -> Terminal {
-        Terminal::new((64, 32))
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        kitty::disable();
     }
 }
 
-/// Provide from_nanos to std::time::Duration
-mod from_nanos {
-    use std::time::Duration;
-
-    const NANOS_PER_SEC: u64 = 1_000_000_000;
-
-    pub trait FromNanos {
-        fn from_nanos(nanos: u64) -> Duration;
-    }
-
-    impl FromNanos for Duration {
-        fn from_nanos(nanos: u64) -> Duration {
-            let secs = nanos / NANOS_PER_SEC;
-            let nanos = (nanos % NANOS_PER_SEC) as u32;
-            Duration::new(secs, nanos)
-        }
+impl Default for Terminal {
+    fn default() -> Terminal {
+        Terminal::new()
     }
 }
-
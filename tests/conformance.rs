@@ -0,0 +1,74 @@
+//! Runs the test ROMs in `tests/roms` headlessly with every quirk preset,
+//! comparing the final screen to the image in `tests/golden` named after
+//! the ROM and the preset.
+//!
+//! Run with `CHIP8_BLESS=1` to write the images from the current
+//! behavior instead, after checking them by eye.
+
+extern crate chip;
+extern crate headless;
+
+use chip::{Chip, Quirks, Status};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CYCLES: usize = 1000;
+const FRAMES: u64 = 120;
+
+#[test]
+fn opcodes() {
+    conform("opcodes");
+}
+
+#[test]
+fn flags() {
+    conform("flags");
+}
+
+#[test]
+fn quirks() {
+    conform("quirks");
+}
+
+#[test]
+fn display() {
+    conform("display");
+}
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+/// The screen as a PBM image after running the ROM with `quirks`.
+fn run(rom: &[u8], quirks: Quirks) -> Vec<u8> {
+    let mut chip = Chip::default();
+    chip.set_quirks(quirks);
+    chip.load(0x200, rom);
+    let mut p = headless::Peripheral::new(headless::Script::default(), Some(FRAMES));
+    while chip.frame(CYCLES, &mut p).unwrap() == Status::Running {}
+    headless::pbm(chip.screen())
+}
+
+fn conform(name: &str) {
+    let path = tests_dir().join("roms").join(name).with_extension("8o");
+    let src = fs::read_to_string(&path).unwrap();
+    let rom = chip::assemble(&src).unwrap_or_else(|e| panic!("{}:{}", path.display(), e));
+    let bless = env::var_os("CHIP8_BLESS").is_some();
+
+    let mut failed = vec![];
+    for &(preset, quirks) in Quirks::PRESETS {
+        let screen = run(&rom, quirks);
+        let golden = tests_dir().join("golden").join(format!("{}.{}.pbm", name, preset));
+        if bless {
+            fs::write(&golden, &screen).unwrap();
+            continue;
+        }
+        match fs::read(&golden) {
+            Ok(ref expected) if *expected == screen => (),
+            Ok(_) => failed.push(format!("{}:\n{}", golden.display(), String::from_utf8_lossy(&screen))),
+            Err(e) => failed.push(format!("{}: {}", golden.display(), e)),
+        }
+    }
+    assert!(failed.is_empty(), "screens differing from the golden images:\n{}", failed.join("\n"));
+}